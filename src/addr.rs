@@ -0,0 +1,296 @@
+use std::{cmp, fmt, str};
+use std::hash::{Hash, Hasher};
+
+use idna;
+
+use errors::AddressParseError;
+
+/// Maximum length of the local part, in bytes, see RFC 5321 section 4.5.3.1.
+const MAX_LOCAL_LENGTH: usize = 64;
+/// Maximum length of an address, in bytes, see RFC 5321 section 4.5.3.1.
+const MAX_LENGTH: usize = 254;
+
+/// An email address, in `addr-spec` form as described in RFC 5322.
+///
+/// Internationalized addresses (RFC 6532) are accepted, with non ASCII
+/// domains converted to their IDNA ASCII form for comparison.
+///
+/// The address keeps the text it was parsed from, which is what `Display`
+/// writes. Comparison and hashing use the normalized form instead, with the
+/// domain lowercased and the local part compared case insensitively, as
+/// Autocrypt requires. This makes `EmailAddress` suitable as the key under
+/// which peers are stored.
+#[derive(Debug, Clone)]
+pub struct EmailAddress {
+    addr: String,
+    /// Position of the `@` separating local part and domain.
+    at: usize,
+    normalized: String,
+    /// Position of the `@` in `normalized`.
+    normalized_at: usize,
+}
+
+impl EmailAddress {
+    /// Parse an address of the form `local@domain`, without display name or
+    /// angle brackets.
+    pub fn parse(s: &str) -> Result<EmailAddress, AddressParseError> {
+        if s.is_empty() {
+            return Err(AddressParseError::Empty);
+        }
+        let at = s.rfind('@').ok_or(AddressParseError::MissingAt)?;
+        let (local, domain) = (&s[..at], &s[at + 1..]);
+
+        let local = normalize_local(local)
+            .ok_or_else(|| AddressParseError::InvalidLocalPart(local.to_string()))?;
+        if local.len() > MAX_LOCAL_LENGTH {
+            return Err(AddressParseError::InvalidLocalPart(s[..at].to_string()));
+        }
+        let domain = normalize_domain(domain)
+            .ok_or_else(|| AddressParseError::InvalidDomain(domain.to_string()))?;
+
+        let normalized = format!("{}@{}", local, domain);
+        if normalized.len() > MAX_LENGTH {
+            return Err(AddressParseError::TooLong(normalized.len()));
+        }
+
+        Ok(EmailAddress {
+               addr: s.to_string(),
+               at,
+               normalized_at: local.len(),
+               normalized,
+           })
+    }
+
+    /// The address as it was parsed.
+    pub fn as_str(&self) -> &str {
+        &self.addr
+    }
+
+    /// The local part, as it was parsed.
+    pub fn local_part(&self) -> &str {
+        &self.addr[..self.at]
+    }
+
+    /// The normalized domain, lowercased and in IDNA ASCII form.
+    pub fn domain(&self) -> &str {
+        &self.normalized[self.normalized_at + 1..]
+    }
+
+    /// The domain in Unicode form, for display.
+    pub fn unicode_domain(&self) -> String {
+        if self.domain().starts_with('[') {
+            return self.domain().to_string();
+        }
+        idna::domain_to_unicode(self.domain()).0
+    }
+
+    /// The normalized address, used for comparison.
+    ///
+    /// The local part is lowercased and stripped of unnecessary quoting, and
+    /// the domain is in the form returned by `domain`.
+    pub fn normalized(&self) -> &str {
+        &self.normalized
+    }
+
+    pub fn into_string(self) -> String {
+        self.addr
+    }
+}
+
+impl PartialEq for EmailAddress {
+    fn eq(&self, other: &EmailAddress) -> bool {
+        self.normalized == other.normalized
+    }
+}
+
+impl Eq for EmailAddress {}
+
+/// Compares with the normalized form of `other`, an invalid address is never
+/// equal.
+impl PartialEq<str> for EmailAddress {
+    fn eq(&self, other: &str) -> bool {
+        EmailAddress::parse(other).map(|other| *self == other).unwrap_or(false)
+    }
+}
+
+impl<'a> PartialEq<&'a str> for EmailAddress {
+    fn eq(&self, other: &&'a str) -> bool {
+        *self == **other
+    }
+}
+
+impl Hash for EmailAddress {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.normalized.hash(state)
+    }
+}
+
+impl PartialOrd for EmailAddress {
+    fn partial_cmp(&self, other: &EmailAddress) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for EmailAddress {
+    fn cmp(&self, other: &EmailAddress) -> cmp::Ordering {
+        self.normalized.cmp(&other.normalized)
+    }
+}
+
+impl fmt::Display for EmailAddress {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", self.addr)
+    }
+}
+
+impl str::FromStr for EmailAddress {
+    type Err = AddressParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        EmailAddress::parse(s)
+    }
+}
+
+impl AsRef<str> for EmailAddress {
+    fn as_ref(&self) -> &str {
+        &self.addr
+    }
+}
+
+/// `atext` of RFC 5322, extended with non ASCII characters by RFC 6532.
+fn is_atext(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-/=?^_`{|}~".contains(c) || !c.is_ascii()
+}
+
+fn is_dot_atom(s: &str) -> bool {
+    !s.is_empty() && s.split('.').all(|atom| !atom.is_empty() && atom.chars().all(is_atext))
+}
+
+/// Validate a local part and bring it into its lowercase, minimally quoted
+/// form.
+fn normalize_local(local: &str) -> Option<String> {
+    if is_dot_atom(local) {
+        return Some(local.to_lowercase());
+    }
+
+    // quoted-string, without folding whitespace
+    if local.len() < 2 || !local.starts_with('"') || !local.ends_with('"') {
+        return None;
+    }
+    let mut content = String::new();
+    let mut chars = local[1..local.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => chars.next()?,
+            '"' => return None,
+            c => c,
+        };
+        if c.is_control() {
+            return None;
+        }
+        content.push(c);
+    }
+
+    let content = content.to_lowercase();
+    if is_dot_atom(&content) {
+        return Some(content);
+    }
+    let escaped: String = content
+        .chars()
+        .flat_map(|c| {
+                      let escape = if c == '"' || c == '\\' { Some('\\') } else { None };
+                      escape.into_iter().chain(Some(c))
+                  })
+        .collect();
+    Some(format!("\"{}\"", escaped))
+}
+
+/// Validate a domain and bring it into its lowercase IDNA ASCII form.
+fn normalize_domain(domain: &str) -> Option<String> {
+    if domain.starts_with('[') {
+        // domain-literal, an address such as `[192.0.2.1]` or `[IPv6:...]`
+        let ok = domain.len() > 2 && domain.ends_with(']') &&
+                 domain[1..domain.len() - 1]
+                     .bytes()
+                     .all(|b| (33..=90).contains(&b) || (94..=126).contains(&b));
+        return if ok { Some(domain.to_lowercase()) } else { None };
+    }
+
+    if !is_dot_atom(domain) {
+        return None;
+    }
+    idna::domain_to_ascii_strict(domain)
+        .ok()
+        .filter(|d| !d.is_empty() && d.split('.').all(|l| !l.starts_with('-') && !l.ends_with('-')))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use helpers::addr;
+
+    #[test]
+    fn test_parse() {
+        let a = addr("Alice.Smith@Example.ORG");
+        assert_eq!(a.as_str(), "Alice.Smith@Example.ORG");
+        assert_eq!(a.to_string(), "Alice.Smith@Example.ORG");
+        assert_eq!(a.local_part(), "Alice.Smith");
+        assert_eq!(a.domain(), "example.org");
+        assert_eq!(a.normalized(), "alice.smith@example.org");
+
+        assert_eq!(addr("\"john doe\"@mail.com").normalized(), "\"john doe\"@mail.com");
+        assert_eq!(addr("\"Bob\"@mail.com").normalized(), "bob@mail.com");
+        assert_eq!(addr("\"a\\\"b\"@mail.com").normalized(), "\"a\\\"b\"@mail.com");
+        assert_eq!(addr("\"a@b\"@mail.com").local_part(), "\"a@b\"");
+        assert_eq!(addr("me@[192.0.2.1]").domain(), "[192.0.2.1]");
+        assert_eq!(addr("me+tag@localhost").domain(), "localhost");
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert_eq!(EmailAddress::parse(""), Err(AddressParseError::Empty));
+        assert_eq!(EmailAddress::parse("mail.com"), Err(AddressParseError::MissingAt));
+        assert_eq!(EmailAddress::parse("@mail.com"),
+                   Err(AddressParseError::InvalidLocalPart("".to_string())));
+        assert_eq!(EmailAddress::parse("me@"),
+                   Err(AddressParseError::InvalidDomain("".to_string())));
+
+        let invalid = ["a..b@mail.com", ".a@mail.com", "a b@mail.com", "<a@mail.com>",
+                       "a@mail..com", "a@-mail.com", "a@mail_x.com", "a@[1.2.3.4",
+                       "\"a\"b@mail.com", "a@b@mail.com", " a@mail.com"];
+        for s in invalid.iter() {
+            assert!(EmailAddress::parse(s).is_err(), "{}", s);
+        }
+
+        let local = "a".repeat(65);
+        assert_eq!(EmailAddress::parse(&format!("{}@mail.com", local)),
+                   Err(AddressParseError::InvalidLocalPart(local)));
+        let domain = vec!["a".repeat(60); 4].join(".");
+        assert!(EmailAddress::parse(&format!("me@{}.com", domain)).is_ok());
+        assert_eq!(EmailAddress::parse(&format!("{}@{}.com", "a".repeat(20), domain)),
+                   Err(AddressParseError::TooLong(268)));
+    }
+
+    #[test]
+    fn test_idna() {
+        let a = addr("Jörg@Bücher.Example");
+        assert_eq!(a.domain(), "xn--bcher-kva.example");
+        assert_eq!(a.unicode_domain(), "bücher.example");
+        assert_eq!(a.normalized(), "jörg@xn--bcher-kva.example");
+        assert_eq!(a, addr("JÖRG@xn--bcher-kva.example"));
+    }
+
+    #[test]
+    fn test_eq() {
+        assert_eq!(addr("Alice@Mail.com"), addr("alice@mail.COM"));
+        assert_ne!(addr("alice@mail.com"), addr("bob@mail.com"));
+        assert!(addr("Alice@Mail.com") == "alice@mail.com");
+        assert!(addr("alice@mail.com") != "not an address");
+
+        let set: HashSet<_> = vec![addr("alice@mail.com"), addr("ALICE@mail.com")]
+            .into_iter()
+            .collect();
+        assert_eq!(set.len(), 1);
+    }
+}
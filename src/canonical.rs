@@ -0,0 +1,340 @@
+//! Canonical binary encoding of headers.
+//!
+//! The encoding starts with the magic `ACH` and a version byte, currently
+//! `1`, followed by
+//!
+//! - the spec version, `0` for `Draft` and `1` for `Level1`
+//! - `addr`, normalized as by `EmailAddress::normalized`
+//! - the key type, as serialized in a header
+//! - the encryption preference, `0` for `nopreference` and `1` for `mutual`
+//! - the decoded `keydata`
+//! - the number of attributes, followed by the attributes in the order
+//!   `Header::attributes` returns them. Each one is a tag, `0` to `3` for
+//!   `addr`, `type`, `prefer-encrypt` and `keydata`, whose values are given
+//!   above, or `4` followed by name and value of a non default attribute.
+//!
+//! Numbers are single bytes, except for counts and lengths, which are 32 bit
+//! big endian. Strings and binary data are prefixed with their length.
+//!
+//! Headers which are equal encode to the same bytes, and the decoder only
+//! accepts bytes as produced by the encoder.
+
+use std::fmt;
+
+use sha2::{Digest, Sha256};
+
+use addr::EmailAddress;
+use header::{self, Header};
+use keydata::KeyData;
+use types::{KeyType, EncryptPreference, SpecVersion};
+use errors::CanonicalDecodeError;
+
+const MAGIC: &[u8] = b"ACH";
+const VERSION: u8 = 1;
+
+const TAG_ADDR: u8 = 0;
+const TAG_TYPE: u8 = 1;
+const TAG_PREFER_ENCRYPT: u8 = 2;
+const TAG_KEYDATA: u8 = 3;
+const TAG_OTHER: u8 = 4;
+
+/// The SHA-256 hash of the canonical encoding of a header.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Hash)]
+pub struct ContentHash([u8; 32]);
+
+impl ContentHash {
+    pub fn new(bytes: [u8; 32]) -> ContentHash {
+        ContentHash(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Lowercase hex, without any separators.
+    pub fn to_hex(&self) -> String {
+        self.0.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+impl fmt::Display for ContentHash {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", self.to_hex())
+    }
+}
+
+impl Header {
+    /// Encode the header in its canonical binary form, see the `canonical`
+    /// module.
+    pub fn to_canonical_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.push(VERSION);
+        out.push(match self.spec {
+                     SpecVersion::Draft => 0,
+                     SpecVersion::Level1 => 1,
+                 });
+        write_bytes(&mut out, self.addr.normalized().as_bytes());
+        write_bytes(&mut out, self.typ.to_string().as_bytes());
+        out.push(match self.prefer_encrypt {
+                     EncryptPreference::None => 0,
+                     EncryptPreference::Mutual => 1,
+                 });
+        write_bytes(&mut out, self.keydata.as_bytes());
+
+        let attributes: Vec<_> = self.attributes().collect();
+        write_u32(&mut out, attributes.len());
+        for (key, value) in attributes {
+            match key {
+                "addr" => out.push(TAG_ADDR),
+                "type" => out.push(TAG_TYPE),
+                "prefer-encrypt" => out.push(TAG_PREFER_ENCRYPT),
+                "keydata" => out.push(TAG_KEYDATA),
+                _ => {
+                    out.push(TAG_OTHER);
+                    write_bytes(&mut out, key.as_bytes());
+                    write_bytes(&mut out, value.as_bytes());
+                }
+            }
+        }
+
+        out
+    }
+
+    /// Decode a header from its canonical binary form.
+    pub fn from_canonical_bytes(bytes: &[u8]) -> Result<Header, CanonicalDecodeError> {
+        let mut reader = Reader(bytes);
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(CanonicalDecodeError::InvalidMagic);
+        }
+        let version = reader.byte()?;
+        if version != VERSION {
+            return Err(CanonicalDecodeError::UnsupportedVersion(version));
+        }
+
+        let spec = match reader.byte()? {
+            0 => SpecVersion::Draft,
+            1 => SpecVersion::Level1,
+            _ => return Err(CanonicalDecodeError::InvalidValue("spec")),
+        };
+        let addr = EmailAddress::parse(reader.string()?)?;
+        let typ: KeyType = reader
+            .string()?
+            .parse()
+            .map_err(|_| CanonicalDecodeError::InvalidValue("type"))?;
+        let pref = match reader.byte()? {
+            0 => EncryptPreference::None,
+            1 => EncryptPreference::Mutual,
+            _ => return Err(CanonicalDecodeError::InvalidValue("prefer-encrypt")),
+        };
+        let keydata = KeyData::new(reader.bytes()?.to_vec());
+
+        let count = reader.u32()?;
+        let mut attributes = Vec::new();
+        for _ in 0..count {
+            let name = match reader.byte()? {
+                TAG_ADDR => "addr",
+                TAG_TYPE => "type",
+                TAG_PREFER_ENCRYPT => "prefer-encrypt",
+                TAG_KEYDATA => "keydata",
+                TAG_OTHER => {
+                    let (name, value) = (reader.string()?, reader.string()?);
+                    header::check_attribute(name, value)
+                        .map_err(|_| CanonicalDecodeError::InvalidValue("attribute"))?;
+                    attributes.push((name.to_string(), value.to_string()));
+                    continue;
+                }
+                _ => return Err(CanonicalDecodeError::InvalidValue("attribute tag")),
+            };
+            attributes.push((name.to_string(), String::new()));
+        }
+        if !reader.0.is_empty() {
+            return Err(CanonicalDecodeError::TrailingData(reader.0.len()));
+        }
+
+        let header = Header::with_layout(addr, typ, pref, keydata, spec, attributes);
+        // rejects anything the encoder would not produce, such as attributes
+        // that are missing or out of place
+        if header.to_canonical_bytes() != bytes {
+            return Err(CanonicalDecodeError::NonCanonical);
+        }
+
+        Ok(header)
+    }
+
+    /// A stable identifier of the contents of this header, the SHA-256 hash
+    /// of `to_canonical_bytes`.
+    pub fn content_hash(&self) -> ContentHash {
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(&Sha256::digest(self.to_canonical_bytes()));
+        ContentHash(hash)
+    }
+}
+
+fn write_u32(out: &mut Vec<u8>, n: usize) {
+    out.extend_from_slice(&(n as u32).to_be_bytes());
+}
+
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_u32(out, bytes.len());
+    out.extend_from_slice(bytes);
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], CanonicalDecodeError> {
+        if self.0.len() < len {
+            return Err(CanonicalDecodeError::UnexpectedEnd);
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn byte(&mut self) -> Result<u8, CanonicalDecodeError> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u32(&mut self) -> Result<usize, CanonicalDecodeError> {
+        let b = self.take(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize)
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], CanonicalDecodeError> {
+        let len = self.u32()?;
+        self.take(len)
+    }
+
+    fn string(&mut self) -> Result<&'a str, CanonicalDecodeError> {
+        ::std::str::from_utf8(self.bytes()?).map_err(|_| CanonicalDecodeError::InvalidUtf8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use helpers::addr;
+    use options::ParseOptions;
+
+    fn header() -> Header {
+        let raw = "_x=1; addr=Me@Mail.com; prefer-encrypt=mutual; _foo=one; keydata=bXlrZXk=; _foo=two";
+        Header::parse_with_options(raw, &ParseOptions::lenient())
+            .expect("failed to parse")
+            .value
+    }
+
+    #[test]
+    fn test_canonical_bytes() {
+        let h = Header::new(addr("me@mail.com"),
+                            KeyType::OpenPGP,
+                            EncryptPreference::Mutual,
+                            KeyData::new(b"mykey".to_vec()),
+                            vec![("_a".to_string(), "b".to_string())]).unwrap();
+
+        let mut expected = b"ACH\x01\x01".to_vec();
+        expected.extend_from_slice(b"\0\0\0\x0bme@mail.com");
+        expected.extend_from_slice(b"\0\0\0\x011");
+        expected.extend_from_slice(b"\x01");
+        expected.extend_from_slice(b"\0\0\0\x05mykey");
+        expected.extend_from_slice(b"\0\0\0\x04\x00\x02\x04\0\0\0\x02_a\0\0\0\x01b\x03");
+        assert_eq!(h.to_canonical_bytes(), expected);
+        assert_eq!(Header::from_canonical_bytes(&expected).unwrap(), h);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let h = header();
+        let decoded = Header::from_canonical_bytes(&h.to_canonical_bytes()).unwrap();
+        assert_eq!(decoded, h);
+        assert_eq!(decoded.addr.as_str(), "me@mail.com");
+        assert_eq!(decoded.to_string(), h.to_string().replace("Me@Mail.com", "me@mail.com"));
+        assert_eq!(decoded.content_hash(), h.content_hash());
+
+        let mut draft = header();
+        draft.spec = SpecVersion::Draft;
+        let decoded = Header::from_canonical_bytes(&draft.to_canonical_bytes()).unwrap();
+        assert_eq!(decoded, draft);
+        assert!(decoded.to_string().contains("addr=me@mail.com;"));
+    }
+
+    #[test]
+    fn test_content_hash() {
+        let h = header();
+        assert_eq!(h.content_hash(), h.clone().content_hash());
+        assert_eq!(h.content_hash().to_hex().len(), 64);
+        assert_eq!(h.content_hash().to_string(), h.content_hash().to_hex());
+
+        // equal headers hash the same, regardless of how they were created
+        let built = Header::builder()
+            .addr("me@mail.com")
+            .keydata(KeyData::new(b"mykey".to_vec()))
+            .attribute("_foo", "one")
+            .build()
+            .unwrap();
+        let parsed: Header = "addr=me@mail.com; _foo=one; keydata=bXlrZXk=".parse().unwrap();
+        assert_eq!(built, parsed);
+        assert_eq!(built.content_hash(), parsed.content_hash());
+
+        let mut other = parsed.clone();
+        other.prefer_encrypt = EncryptPreference::Mutual;
+        assert!(other.content_hash() != parsed.content_hash());
+        let reordered: Header = "addr=me@mail.com; keydata=bXlrZXk=; _foo=one".parse().unwrap();
+        assert!(reordered.content_hash() != parsed.content_hash());
+    }
+
+    #[test]
+    fn test_content_hash_addr_case() {
+        let lower: Header = "addr=me@mail.com; keydata=bXlrZXk=".parse().unwrap();
+        let upper: Header = "addr=Me@Mail.com; keydata=bXlrZXk=".parse().unwrap();
+        assert_eq!(lower, upper);
+        assert_eq!(lower.to_canonical_bytes(), upper.to_canonical_bytes());
+        assert_eq!(lower.content_hash(), upper.content_hash());
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let bytes = header().to_canonical_bytes();
+
+        assert_eq!(Header::from_canonical_bytes(b"ACX\x01").err(),
+                   Some(CanonicalDecodeError::InvalidMagic));
+        assert_eq!(Header::from_canonical_bytes(b"ACH\x02").err(),
+                   Some(CanonicalDecodeError::UnsupportedVersion(2)));
+        assert_eq!(Header::from_canonical_bytes(&bytes[..bytes.len() - 1]).err(),
+                   Some(CanonicalDecodeError::UnexpectedEnd));
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(Header::from_canonical_bytes(&trailing).err(),
+                   Some(CanonicalDecodeError::TrailingData(1)));
+
+        let mut spec = bytes.clone();
+        spec[4] = 7;
+        assert_eq!(Header::from_canonical_bytes(&spec).err(),
+                   Some(CanonicalDecodeError::InvalidValue("spec")));
+
+        // a Level 1 header never carries the type attribute
+        let count_at = 5 + (4 + 11) + (4 + 1) + 1 + (4 + 5);
+        assert_eq!(bytes[count_at..count_at + 4], [0, 0, 0, 6]);
+        let mut with_type = bytes.clone();
+        with_type[count_at + 3] = 7;
+        with_type.insert(count_at + 4, TAG_TYPE);
+        assert_eq!(Header::from_canonical_bytes(&with_type).err(),
+                   Some(CanonicalDecodeError::NonCanonical));
+    }
+
+    #[test]
+    fn test_decode_invalid_attribute() {
+        for &(name, value) in &[("_foo", "x; prefer-encrypt=mutual"),
+                                ("_foo", "a\r\n b"),
+                                ("_a=b", "x")] {
+            // the encoder does not check the attributes
+            let h = Header::new_unchecked(addr("me@mail.com"),
+                                          KeyType::OpenPGP,
+                                          EncryptPreference::None,
+                                          KeyData::new(b"mykey".to_vec()),
+                                          vec![(name.to_string(), value.to_string())]);
+            assert_eq!(Header::from_canonical_bytes(&h.to_canonical_bytes()).err(),
+                       Some(CanonicalDecodeError::InvalidValue("attribute")));
+        }
+    }
+}
@@ -0,0 +1,104 @@
+//! The interface to the OpenPGP implementation doing the actual
+//! cryptography.
+//!
+//! Anything producing or consuming encrypted mail is given a
+//! `CryptoBackend`, so applications can plug in the OpenPGP stack of their
+//! choice. The `openpgp` feature adds `OpenPgpBackend`, built on the pure
+//! Rust rPGP library, and the `mock` feature `MockBackend`, a fake one for
+//! tests.
+
+use addr::EmailAddress;
+use errors::{KeyValidationError, Result};
+use keydata::KeyData;
+use pgp::{Fingerprint, KeyId};
+
+/// The kind of key to generate.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum KeyAlgorithm {
+    /// An Ed25519 primary key with an X25519 encryption subkey, as
+    /// recommended by Autocrypt Level 1.
+    #[default]
+    Ed25519,
+    /// An RSA primary key and encryption subkey of the given size in bits.
+    Rsa(usize),
+}
+
+/// A newly generated key.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct KeyPair {
+    /// The transferable public key, as sent in `keydata`.
+    pub public: KeyData,
+    /// The transferable secret key, unprotected.
+    pub secret: KeyData,
+}
+
+/// The result of checking the signature of a decrypted message.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum SignatureStatus {
+    /// The message was not signed.
+    Unsigned,
+    /// The message carries a valid signature by the key with this
+    /// fingerprint, the primary key of one of the senders.
+    Valid(Fingerprint),
+    /// The message was signed by a key other than the ones of the senders.
+    UnknownKey(KeyId),
+    /// The signature does not verify.
+    Invalid,
+}
+
+/// A decrypted OpenPGP message.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Decrypted {
+    pub data: Vec<u8>,
+    pub signature: SignatureStatus,
+}
+
+/// An OpenPGP implementation.
+///
+/// Public keys are passed as binary transferable public keys, as carried in
+/// `keydata`, and secret keys as binary transferable secret keys, as
+/// returned by `generate_key`. The data is usually a MIME entity, as
+/// encrypted or signed by PGP/MIME. Failures of the backend are reported as
+/// `Error::Crypto`, see `Error::crypto`.
+pub trait CryptoBackend {
+    /// Generate a new key with a user id for `addr`.
+    fn generate_key(&self, addr: &EmailAddress, algorithm: KeyAlgorithm) -> Result<KeyPair>;
+
+    /// Encrypt `data` to all of `recipients`, returning an ASCII armored
+    /// OpenPGP message.
+    ///
+    /// The message is signed by `signer`, a secret key, if given.
+    fn encrypt(&self, data: &[u8], recipients: &[KeyData], signer: Option<&KeyData>)
+               -> Result<String>;
+
+    /// Decrypt an OpenPGP message, binary or ASCII armored, with
+    /// `secret_key`.
+    ///
+    /// A signature is checked against the public keys of `senders`.
+    fn decrypt(&self, message: &[u8], secret_key: &KeyData, senders: &[KeyData])
+               -> Result<Decrypted>;
+
+    /// Encrypt `data` with a passphrase, as done for the Autocrypt Setup
+    /// Message, returning an ASCII armored OpenPGP message.
+    fn encrypt_symmetric(&self, data: &[u8], passphrase: &str) -> Result<String>;
+
+    /// Decrypt a passphrase encrypted OpenPGP message, binary or ASCII
+    /// armored, such as an Autocrypt Setup Message.
+    fn decrypt_symmetric(&self, message: &[u8], passphrase: &str) -> Result<Vec<u8>>;
+
+    /// Create an ASCII armored detached signature of `data`.
+    fn sign(&self, data: &[u8], secret_key: &KeyData) -> Result<String>;
+
+    /// Check a detached signature, binary or ASCII armored, of `data`
+    /// against the public keys of `senders`.
+    fn verify(&self, data: &[u8], signature: &[u8], senders: &[KeyData])
+              -> Result<SignatureStatus>;
+
+    /// Check that a transferable public key passes `validate_key` with
+    /// signatures that actually verify: the primary key is neither revoked
+    /// nor expired, and a user id and an encryption subkey are bound to it.
+    ///
+    /// Any failure makes the key unusable, so it is reported as a
+    /// `KeyValidationError`, signatures that do not verify as `Unverified`.
+    fn verify_key(&self, key: &KeyData) -> ::std::result::Result<(), KeyValidationError>;
+}
@@ -1,37 +1,483 @@
+use std::error::Error as StdError;
+
 use email;
+use pgp::{PublicKeyAlgorithm, Fingerprint};
+use options::ParseWarning;
 
 quick_error! {
+    /// Errors parsing an Autocrypt header.
+    ///
+    /// Offsets are in bytes, relative to the start of the header value.
     #[derive(PartialEq, Eq, Debug, Clone)]
     pub enum HeaderParseError {
-        UnknownCriticalAttributes {}
-        MissingCriticalAttribute(descr: &'static str) {
-            description(descr)
-            display("Missing Critical Attribute: {}", descr)
+        UnknownCriticalAttribute { name: String, offset: usize } {
+            display("Unknown critical attribute {:?} at offset {}, only addr, prefer-encrypt \
+                     and keydata are allowed, others must start with '_'",
+                    name, offset)
+        }
+        MissingCriticalAttribute(name: &'static str) {
+            display("Missing critical attribute {:?}", name)
+        }
+        MissingHeader {
+            display("Missing Autocrypt header")
+        }
+        HeaderTooLarge { size: usize, limit: usize } {
+            display("Header is {} bytes, at most {} are allowed", size, limit)
+        }
+        TooManyAttributes { limit: usize, offset: usize } {
+            display("More than {} attributes, the first excess one at offset {}", limit, offset)
+        }
+        AttributeTooLong { name: String, length: usize, limit: usize, offset: usize } {
+            display("Attribute {:?} at offset {} is {} bytes, at most {} are allowed",
+                    name, offset, length, limit)
+        }
+        KeyTooLarge { size: usize, limit: usize, offset: usize } {
+            display("Key at offset {} is {} bytes, at most {} are allowed", offset, size, limit)
+        }
+        TooManyHeaders { count: usize, limit: usize } {
+            display("Email has {} Autocrypt headers, at most {} are allowed", count, limit)
+        }
+        InvalidHeader(reason: String) {
+            display("Invalid header: {}", reason)
+        }
+        Rejected { warning: ParseWarning, offset: usize } {
+            display("Rejected by strict parsing at offset {}: {}", offset, warning)
+        }
+        InvalidAddress { err: AddressParseError, offset: usize } {
+            source(err)
+            display("Invalid addr at offset {}: {}", offset, err)
+        }
+        InvalidKeyData { err: KeyDataParseError, offset: usize } {
+            source(err)
+            display("Invalid keydata at offset {}: {}", offset, err)
+        }
+        InvalidKey(err: KeyValidationError) {
+            from()
+            source(err)
+            display("Invalid key: {}", err)
+        }
+    }
+}
+
+impl HeaderParseError {
+    /// The name of the offending attribute, if the error is about a single
+    /// attribute.
+    pub fn attribute(&self) -> Option<&str> {
+        match *self {
+            HeaderParseError::UnknownCriticalAttribute { ref name, .. } => Some(name),
+            HeaderParseError::MissingCriticalAttribute(name) => Some(name),
+            HeaderParseError::Rejected { ref warning, .. } => warning.attribute(),
+            HeaderParseError::InvalidAddress { .. } => Some("addr"),
+            HeaderParseError::AttributeTooLong { ref name, .. } => Some(name),
+            HeaderParseError::KeyTooLarge { .. } => Some("keydata"),
+            HeaderParseError::InvalidKeyData { .. } |
+            HeaderParseError::InvalidKey(_) => Some("keydata"),
+            HeaderParseError::MissingHeader |
+            HeaderParseError::InvalidHeader(_) |
+            HeaderParseError::HeaderTooLarge { .. } |
+            HeaderParseError::TooManyHeaders { .. } |
+            HeaderParseError::TooManyAttributes { .. } => None,
+        }
+    }
+
+    /// The byte offset into the header value the error occurred at, if known.
+    pub fn offset(&self) -> Option<usize> {
+        match *self {
+            HeaderParseError::UnknownCriticalAttribute { offset, .. } |
+            HeaderParseError::Rejected { offset, .. } |
+            HeaderParseError::InvalidAddress { offset, .. } |
+            HeaderParseError::TooManyAttributes { offset, .. } |
+            HeaderParseError::AttributeTooLong { offset, .. } |
+            HeaderParseError::KeyTooLarge { offset, .. } |
+            HeaderParseError::InvalidKeyData { offset, .. } => Some(offset),
+            _ => None,
+        }
+    }
+}
+
+quick_error! {
+    #[derive(PartialEq, Eq, Debug, Clone)]
+    pub enum HeaderBuildError {
+        MissingAttribute(name: &'static str) {
+            display("Missing attribute: {}", name)
+        }
+        InvalidAddress(addr: String) {
+            display("Invalid address: {:?}", addr)
+        }
+        CriticalAttribute(name: String) {
+            display("Attribute {:?} is critical, non-critical attributes must start with '_'", name)
+        }
+        InvalidAttribute(name: String) {
+            display("Invalid attribute: {:?}", name)
+        }
+        AttributeTooLong(name: String) {
+            display("Attribute {:?} does not fit on a line of 998 octets", name)
+        }
+    }
+}
+
+quick_error! {
+    /// Errors parsing an `EmailAddress`.
+    #[derive(PartialEq, Eq, Debug, Clone)]
+    pub enum AddressParseError {
+        Empty {
+            display("Empty address")
+        }
+        MissingAt {
+            display("Missing '@' between local part and domain")
+        }
+        InvalidLocalPart(local: String) {
+            display("Invalid local part: {:?}", local)
+        }
+        InvalidDomain(domain: String) {
+            display("Invalid domain: {:?}", domain)
+        }
+        TooLong(len: usize) {
+            display("Address is {} bytes long, at most 254 are allowed", len)
+        }
+    }
+}
+
+quick_error! {
+    /// Errors decoding the canonical binary form of a header.
+    #[derive(PartialEq, Eq, Debug, Clone)]
+    pub enum CanonicalDecodeError {
+        InvalidMagic {
+            display("Not a canonically encoded header")
+        }
+        UnsupportedVersion(version: u8) {
+            display("Unsupported canonical encoding version {}", version)
+        }
+        UnexpectedEnd {
+            display("Unexpected end of data")
+        }
+        TrailingData(len: usize) {
+            display("{} bytes of trailing data", len)
+        }
+        InvalidUtf8 {
+            display("Invalid UTF-8 in string")
+        }
+        InvalidValue(field: &'static str) {
+            display("Invalid {}", field)
+        }
+        InvalidAddress(err: AddressParseError) {
+            from()
+            source(err)
+            display("Invalid addr: {}", err)
+        }
+        NonCanonical {
+            display("Data is not in canonical form")
+        }
+    }
+}
+
+quick_error! {
+    #[derive(PartialEq, Eq, Debug, Clone)]
+    pub enum KeyDataParseError {
+        Empty {
+            display("Empty key")
+        }
+        InvalidBase64(descr: String) {
+            display("Invalid Base64: {}", descr)
+        }
+    }
+}
+quick_error! {
+    #[derive(PartialEq, Eq, Debug, Clone)]
+    pub enum KeyParseError {
+        UnexpectedEnd {
+            display("Unexpected end of key data")
+        }
+        InvalidPacketHeader {
+            display("Invalid packet header")
+        }
+        UnsupportedLength {
+            display("Partial and indeterminate packet lengths are not supported")
+        }
+        UnsupportedVersion(packet: &'static str, version: u8) {
+            display("Unsupported {} packet version: {}", packet, version)
+        }
+        UnexpectedPacket(tag: u8) {
+            display("Unexpected packet with tag {}", tag)
+        }
+        MissingPrimaryKey {
+            display("Missing primary key")
+        }
+    }
+}
+
+quick_error! {
+    #[derive(PartialEq, Eq, Debug, Clone)]
+    pub enum KeyValidationError {
+        Parse(err: KeyParseError) {
+            from()
+            source(err)
+            display("Failed to parse key: {}", err)
+        }
+        PrimaryCannotSign(algorithm: PublicKeyAlgorithm) {
+            display("Primary key is not signing capable: {}", algorithm)
+        }
+        MissingUserId {
+            display("Missing user id")
+        }
+        NoMatchingUserId(addr: String) {
+            display("No user id matches {}", addr)
+        }
+        MissingSelfSignature {
+            display("No user id carries a valid self signature")
+        }
+        MissingEncryptionSubkey {
+            display("Missing encryption capable subkey")
+        }
+        TooManyEncryptionSubkeys(count: usize) {
+            display("Expected one encryption capable subkey, found {}", count)
+        }
+        MissingBindingSignature(subkey: Fingerprint) {
+            display("Encryption subkey {} has no valid binding signature", subkey)
+        }
+        PrimaryRevoked {
+            display("Primary key is revoked")
+        }
+        PrimaryExpired {
+            display("Primary key has expired")
+        }
+        Unverified(reason: String) {
+            display("Signature check failed: {}", reason)
         }
-        MissingHeader {}
-        TooManyHeaders {}
-        InvalidHeader {}
     }
 }
+
 quick_error! {
     #[derive(PartialEq, Eq, Debug, Clone)]
-    pub enum KeyTypeParseError {}
+    pub enum KeyTypeParseError {
+        Empty {
+            display("Empty key type")
+        }
+    }
 }
 
 quick_error! {
     #[derive(PartialEq, Eq, Debug, Clone)]
-    pub enum EncryptPreferenceParseError {}
+    pub enum EncryptPreferenceParseError {
+        Unknown(value: String) {
+            display("Unknown encryption preference {:?}", value)
+        }
+    }
 }
 
 quick_error! {
     #[derive(PartialEq, Eq, Debug, Clone)]
     pub enum PeerInfoParseError {
-        MimeError {
-            from(email::results::ParsingError)
+        MimeError(descr: String) {
+            from(err: email::results::ParsingError) -> (err.to_string())
+            display("Failed to parse email: {}", descr)
+        }
+
+        HeaderError(err: HeaderParseError) {
+            from()
+            source(err)
+            display("Invalid Autocrypt header: {}", err)
         }
+    }
+}
 
-        HeaderError {
-            from(HeaderParseError)
+quick_error! {
+    /// Errors adding an Autocrypt header to an outgoing email.
+    #[derive(PartialEq, Eq, Debug, Clone)]
+    pub enum HeaderInjectError {
+        MissingFrom {
+            display("Missing or invalid From address")
         }
+        FromMismatch { from: String, addr: String } {
+            display("From address {} does not match the header addr {}", from, addr)
+        }
+    }
+}
+
+quick_error! {
+    /// Errors composing an encrypted email.
+    #[derive(PartialEq, Eq, Debug, Clone)]
+    pub enum EncryptError {
+        MissingKey(addr: String) {
+            display("No public key known for {}", addr)
+        }
+    }
+}
+
+quick_error! {
+    /// Errors decrypting an email.
+    #[derive(PartialEq, Eq, Debug, Clone)]
+    pub enum DecryptError {
+        InvalidStructure(reason: &'static str) {
+            display("Invalid PGP/MIME structure: {}", reason)
+        }
+        InvalidUtf8 {
+            display("Decrypted message is not valid UTF-8")
+        }
+    }
+}
+
+/// The broad category of an `Error`.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ErrorKind {
+    /// An invalid Autocrypt header.
+    Header,
+    /// An email that could not be parsed.
+    Mime,
+    /// Invalid or unusable key material.
+    Key,
+    /// A failure of the storage used for peer state.
+    Storage,
+    /// A failure of the crypto backend.
+    Crypto,
+}
+
+quick_error! {
+    /// Any error produced by this crate.
+    ///
+    /// All other error types convert into this one, keeping the original
+    /// error available through `source`.
+    #[derive(Debug)]
+    pub enum Error {
+        Header(err: HeaderParseError) {
+            from()
+            source(err)
+            display("Invalid Autocrypt header: {}", err)
+        }
+        HeaderBuild(err: HeaderBuildError) {
+            from()
+            source(err)
+            display("Invalid Autocrypt header: {}", err)
+        }
+        Address(err: AddressParseError) {
+            from()
+            source(err)
+            display("Invalid address: {}", err)
+        }
+        Canonical(err: CanonicalDecodeError) {
+            from()
+            source(err)
+            display("Invalid canonical header: {}", err)
+        }
+        Mime(err: email::results::ParsingError) {
+            from()
+            source(err)
+            display("Failed to parse email: {}", err)
+        }
+        HeaderInject(err: HeaderInjectError) {
+            from()
+            source(err)
+            display("Failed to add Autocrypt header: {}", err)
+        }
+        Encrypt(err: EncryptError) {
+            from()
+            source(err)
+            display("Failed to encrypt email: {}", err)
+        }
+        Decrypt(err: DecryptError) {
+            from()
+            source(err)
+            display("Failed to decrypt email: {}", err)
+        }
+        KeyData(err: KeyDataParseError) {
+            from()
+            source(err)
+            display("Invalid keydata: {}", err)
+        }
+        KeyParse(err: KeyParseError) {
+            from()
+            source(err)
+            display("Failed to parse key: {}", err)
+        }
+        KeyValidation(err: KeyValidationError) {
+            from()
+            source(err)
+            display("Invalid key: {}", err)
+        }
+        Storage(err: Box<dyn StdError + Send + Sync>) {
+            source(&**err)
+            display("Storage error: {}", err)
+        }
+        Crypto(err: Box<dyn StdError + Send + Sync>) {
+            source(&**err)
+            display("Crypto error: {}", err)
+        }
+    }
+}
+
+impl Error {
+    /// Wrap an error of the storage used for peer state.
+    pub fn storage<E: Into<Box<dyn StdError + Send + Sync>>>(err: E) -> Error {
+        Error::Storage(err.into())
+    }
+
+    /// Wrap an error of a crypto backend.
+    pub fn crypto<E: Into<Box<dyn StdError + Send + Sync>>>(err: E) -> Error {
+        Error::Crypto(err.into())
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match *self {
+            Error::Header(_) | Error::HeaderBuild(_) | Error::Address(_) | Error::Canonical(_) => {
+                ErrorKind::Header
+            }
+            Error::Mime(_) | Error::HeaderInject(_) | Error::Decrypt(_) => ErrorKind::Mime,
+            Error::Encrypt(_) | Error::KeyData(_) | Error::KeyParse(_) |
+            Error::KeyValidation(_) => ErrorKind::Key,
+            Error::Storage(_) => ErrorKind::Storage,
+            Error::Crypto(_) => ErrorKind::Crypto,
+        }
+    }
+}
+
+impl From<PeerInfoParseError> for Error {
+    fn from(err: PeerInfoParseError) -> Error {
+        match err {
+            PeerInfoParseError::MimeError(descr) => {
+                Error::Mime(email::results::ParsingError::new(descr))
+            }
+            PeerInfoParseError::HeaderError(err) => Error::Header(err),
+        }
+    }
+}
+
+/// A `Result` using this crate's `Error`.
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_send_sync<T: Send + Sync + 'static>() {}
+
+    #[test]
+    fn test_error_send_sync() {
+        assert_send_sync::<Error>();
+        assert_send_sync::<PeerInfoParseError>();
+    }
+
+    #[test]
+    fn test_error_kind() {
+        let err: Error = KeyParseError::UnexpectedEnd.into();
+        assert_eq!(err.kind(), ErrorKind::Key);
+        assert_eq!(err.to_string(), "Failed to parse key: Unexpected end of key data");
+
+        let err = Error::crypto("no secret key");
+        assert_eq!(err.kind(), ErrorKind::Crypto);
+        assert_eq!(err.source().unwrap().to_string(), "no secret key");
+    }
+
+    #[test]
+    fn test_error_from_peer_info() {
+        let err: Error = PeerInfoParseError::HeaderError(HeaderParseError::MissingHeader).into();
+        assert_eq!(err.kind(), ErrorKind::Header);
+        assert!(err.source().unwrap().downcast_ref::<HeaderParseError>().is_some());
+
+        let mime_err = email::results::ParsingError::new("broken".to_string());
+        let err = PeerInfoParseError::from(mime_err);
+        assert_eq!(err.clone(), PeerInfoParseError::MimeError("broken".to_string()));
+        let err: Error = err.into();
+        assert_eq!(err.kind(), ErrorKind::Mime);
+        assert_eq!(err.to_string(), "Failed to parse email: broken");
     }
 }
@@ -1,60 +1,373 @@
 use std::{fmt, str};
-use std::collections::BTreeMap;
+use std::borrow::Cow;
 
-use types::{KeyType, EncryptPreference};
-use errors::HeaderParseError;
+use addr::EmailAddress;
+use types::{KeyType, EncryptPreference, SpecVersion, Dialect};
+use keydata::KeyData;
+use options::{ParseOptions, ParsePolicy, ParseWarning, Parsed};
+use pgp::{TransferablePublicKey, Fingerprint, KeyId};
+use validate::validate_key;
+use errors::{HeaderParseError, HeaderBuildError, KeyDataParseError, KeyParseError,
+             KeyValidationError};
+
+/// Maximum width of the lines of a folded header field, including the
+/// leading whitespace of continuation lines.
+const LINE_WIDTH: usize = 76;
+
+/// Maximum length of a line of an email, without the CRLF, see RFC 5322,
+/// section 2.1.1.
+const MAX_LINE_LENGTH: usize = 998;
 
 /// Represents an Autocrypt Header
-#[derive(PartialEq, Eq, Debug, Clone)]
+#[derive(Eq, Debug, Clone)]
 pub struct Header {
     /// The single recipient email address this header is valid for.
-    pub addr: String,
+    pub addr: EmailAddress,
     /// Key type
     pub typ: KeyType,
     /// Encryption preference,
     pub prefer_encrypt: EncryptPreference,
-    /// Public Key, encoded in Base64
-    pub keydata: String,
-    /// All non default attributes, this is a `BTreeMap` to ensure consistent sorting
-    attributes: BTreeMap<String, String>,
+    /// Public Key, serialized as Base64
+    pub keydata: KeyData,
+    /// The version of the spec this header is parsed from and serialized to.
+    pub spec: SpecVersion,
+    /// The dialect this header was parsed from, it is always serialized
+    /// following `spec`.
+    pub dialect: Dialect,
+    /// The order of all attributes, including the non default ones.
+    attributes: Vec<Attribute<String>>,
+}
+
+/// An entry in the list of attributes of a header.
+///
+/// Critical attributes only mark the position, their value is kept in the
+/// fields of the header.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+enum Attribute<S> {
+    Addr,
+    Type,
+    PreferEncrypt,
+    Keydata,
+    Other(S, S),
+}
+
+impl Attribute<String> {
+    fn as_ref(&self) -> Attribute<&str> {
+        match *self {
+            Attribute::Addr => Attribute::Addr,
+            Attribute::Type => Attribute::Type,
+            Attribute::PreferEncrypt => Attribute::PreferEncrypt,
+            Attribute::Keydata => Attribute::Keydata,
+            Attribute::Other(ref key, ref value) => Attribute::Other(key, value),
+        }
+    }
+}
+
+impl Attribute<&str> {
+    fn into_owned(self) -> Attribute<String> {
+        match self {
+            Attribute::Addr => Attribute::Addr,
+            Attribute::Type => Attribute::Type,
+            Attribute::PreferEncrypt => Attribute::PreferEncrypt,
+            Attribute::Keydata => Attribute::Keydata,
+            Attribute::Other(key, value) => Attribute::Other(key.to_string(), value.to_string()),
+        }
+    }
+}
+
+/// Headers are equal if they have the same fields and serialize to the same
+/// attributes, regardless of where omitted critical attributes would go and
+/// which dialect they were parsed from. Addresses are compared normalized.
+impl PartialEq for Header {
+    fn eq(&self, other: &Header) -> bool {
+        self.addr == other.addr && self.typ == other.typ &&
+        self.prefer_encrypt == other.prefer_encrypt && self.keydata == other.keydata &&
+        self.spec == other.spec &&
+        self.attributes().map(without_addr).eq(other.attributes().map(without_addr))
+    }
+}
+
+/// Drop the value of `addr`, which is compared through the field.
+fn without_addr<'a>((key, value): (&'a str, Cow<'a, str>)) -> (&'a str, Cow<'a, str>) {
+    if key == "addr" {
+        (key, Cow::Borrowed(""))
+    } else {
+        (key, value)
+    }
 }
 
 impl fmt::Display for Header {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        let mut rest = String::new();
-        for (key, value) in self.attributes.iter() {
-            rest = format!("{}{}={}; ", rest, key, value);
+        for (i, (key, value)) in self.attributes().enumerate() {
+            if i > 0 {
+                write!(fmt, "; ")?;
+            }
+            write!(fmt, "{}={}", key, value)?;
         }
 
-        write!(fmt,
-               "addr={}; type={}; prefer-encrypt={}; {}keydata={}",
-               self.addr,
-               self.typ,
-               self.prefer_encrypt,
-               rest,
-               self.keydata)
+        Ok(())
     }
 }
 
 impl Header {
-    pub fn new(addr: String,
-               typ: KeyType,
-               pref: EncryptPreference,
-               key: String,
-               attributes: BTreeMap<String, String>)
-               -> Header {
+    /// Create a new header, the non default `attributes` are placed between
+    /// the critical attributes and `keydata`, in the given order.
+    ///
+    /// Fails if one of the attributes is critical or would break the header
+    /// grammar, like `HeaderBuilder::build` does.
+    pub fn new<I>(addr: EmailAddress,
+                  typ: KeyType,
+                  pref: EncryptPreference,
+                  key: KeyData,
+                  attributes: I)
+                  -> Result<Header, HeaderBuildError>
+        where I: IntoIterator<Item = (String, String)>
+    {
+        let attributes: Vec<_> = attributes.into_iter().collect();
+        for (key, value) in &attributes {
+            check_attribute(key, value)?;
+        }
+
+        Ok(Header::new_unchecked(addr, typ, pref, key, attributes))
+    }
+
+    /// Create a new header like `new`, without checking the attributes.
+    pub(crate) fn new_unchecked<I>(addr: EmailAddress,
+                                   typ: KeyType,
+                                   pref: EncryptPreference,
+                                   key: KeyData,
+                                   attributes: I)
+                                   -> Header
+        where I: IntoIterator<Item = (String, String)>
+    {
+        let mut layout = vec![Attribute::Addr, Attribute::Type, Attribute::PreferEncrypt];
+        layout.extend(attributes.into_iter().map(|(k, v)| Attribute::Other(k, v)));
+        layout.push(Attribute::Keydata);
+
+        Header {
+            addr,
+            typ,
+            prefer_encrypt: pref,
+            keydata: key,
+            spec: SpecVersion::default(),
+            dialect: Dialect::Standard,
+            attributes: layout,
+        }
+    }
+
+    /// Create a header with the attributes in the given order, as returned
+    /// by `attributes`. Only the names of critical attributes are used, their
+    /// values are taken from the fields.
+    pub(crate) fn with_layout<I>(addr: EmailAddress,
+                                 typ: KeyType,
+                                 pref: EncryptPreference,
+                                 key: KeyData,
+                                 spec: SpecVersion,
+                                 attributes: I)
+                                 -> Header
+        where I: IntoIterator<Item = (String, String)>
+    {
+        let layout = attributes
+            .into_iter()
+            .map(|(k, v)| match k.as_str() {
+                     "addr" => Attribute::Addr,
+                     "type" => Attribute::Type,
+                     "prefer-encrypt" => Attribute::PreferEncrypt,
+                     "keydata" => Attribute::Keydata,
+                     _ => Attribute::Other(k, v),
+                 })
+            .collect();
+
         Header {
-            addr: addr,
-            typ: typ,
+            addr,
+            typ,
             prefer_encrypt: pref,
             keydata: key,
-            attributes: attributes,
+            spec,
+            dialect: Dialect::Standard,
+            attributes: layout,
         }
     }
 
+    /// Start building a new header.
+    pub fn builder() -> HeaderBuilder {
+        HeaderBuilder::new()
+    }
+
     /// Get any additional attributes
+    ///
+    /// If an attribute is present more than once, the last value is returned.
     pub fn get<S: Into<String>>(&self, key: S) -> Option<&String> {
-        self.attributes.get(&key.into())
+        let key = key.into();
+        self.attributes
+            .iter()
+            .rev()
+            .filter_map(|a| match *a {
+                            Attribute::Other(ref k, ref v) if *k == key => Some(v),
+                            _ => None,
+                        })
+            .next()
+    }
+
+    /// All attributes, as they are serialized.
+    ///
+    /// Parsed headers keep the order of the original, including duplicate
+    /// non default attributes. Critical attributes which are not part of the
+    /// original are placed right after `addr`, and `keydata` at the end.
+    pub fn attributes<'a>(&'a self) -> ::std::vec::IntoIter<(&'a str, Cow<'a, str>)> {
+        let mut layout: Vec<Attribute<&str>> = self.attributes.iter().map(|a| a.as_ref()).collect();
+        if !layout.contains(&Attribute::Addr) {
+            layout.insert(0, Attribute::Addr);
+        }
+        let after_addr = layout.iter().position(|a| *a == Attribute::Addr).expect("inserted above") + 1;
+        if !layout.contains(&Attribute::PreferEncrypt) {
+            layout.insert(after_addr, Attribute::PreferEncrypt);
+        }
+        if !layout.contains(&Attribute::Type) {
+            layout.insert(after_addr, Attribute::Type);
+        }
+        if !layout.contains(&Attribute::Keydata) {
+            layout.push(Attribute::Keydata);
+        }
+
+        let draft = self.spec == SpecVersion::Draft;
+        layout.into_iter()
+            .filter_map(|a| match a {
+                Attribute::Addr => Some(("addr", Cow::Borrowed(self.addr.as_str()))),
+                Attribute::Type if draft => Some(("type", Cow::Owned(self.typ.to_string()))),
+                // Level 1 only knows `mutual`, the absence signals no preference.
+                Attribute::PreferEncrypt if draft || self.prefer_encrypt == EncryptPreference::Mutual => {
+                    Some(("prefer-encrypt", Cow::Owned(self.prefer_encrypt.to_string())))
+                }
+                Attribute::Keydata => Some(("keydata", Cow::Owned(self.keydata.to_base64()))),
+                Attribute::Other(key, value) => Some((key, Cow::Borrowed(value))),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    /// All non default attributes, in order.
+    #[cfg(feature = "serde")]
+    pub(crate) fn non_critical(&self) -> Vec<(&str, &str)> {
+        self.attributes
+            .iter()
+            .filter_map(|a| match *a {
+                            Attribute::Other(ref k, ref v) => Some((k.as_str(), v.as_str())),
+                            _ => None,
+                        })
+            .collect()
+    }
+
+    /// Parse the OpenPGP key carried in `keydata`.
+    pub fn parse_key(&self) -> Result<TransferablePublicKey, KeyParseError> {
+        TransferablePublicKey::from_bytes(self.keydata.as_bytes())
+    }
+
+    /// Check that `keydata` holds a key as required by Autocrypt Level 1.
+    ///
+    /// Only the structure of the key is checked, see `validate_key`.
+    pub fn validate(&self) -> Result<(), KeyValidationError> {
+        validate_key(&self.parse_key()?)
+    }
+
+    /// The fingerprint of the primary key in `keydata`.
+    pub fn fingerprint(&self) -> Result<Fingerprint, KeyParseError> {
+        self.parse_key().map(|key| key.fingerprint())
+    }
+
+    /// The key id of the primary key in `keydata`.
+    pub fn key_id(&self) -> Result<KeyId, KeyParseError> {
+        self.fingerprint().map(|fp| fp.key_id())
+    }
+
+    /// The fingerprints of all subkeys in `keydata`.
+    pub fn subkey_fingerprints(&self) -> Result<Vec<Fingerprint>, KeyParseError> {
+        self.parse_key().map(|key| key.subkey_fingerprints())
+    }
+
+    /// Serialize as a complete `Autocrypt:` header field, folded as described
+    /// in RFC 5322.
+    ///
+    /// Lines are wrapped at 76 columns, with `keydata` starting on a line of
+    /// its own. Other attributes are never split, a long one gets a line of
+    /// its own instead, which `check_attribute` keeps below the limit of 998
+    /// octets. Lines are separated by CRLF followed by a single space, and no
+    /// trailing CRLF is added.
+    pub fn to_folded(&self) -> String {
+        format!("Autocrypt: {}", self.folded_value("Autocrypt"))
+    }
+
+    /// Parse a header field as produced by `to_folded`.
+    ///
+    /// Any folding is accepted, with CRLF, LF or CR line breaks, and the
+    /// `Autocrypt:` field name is optional.
+    pub fn from_folded(s: &str) -> Result<Header, HeaderParseError> {
+        unfold(s, "Autocrypt").parse()
+    }
+
+    /// The folded value of a header field with the given name.
+    pub(crate) fn folded_value(&self, name: &str) -> String {
+        let attributes: Vec<_> = self.attributes().collect();
+        let last = attributes.len() - 1;
+
+        let mut lines = vec![String::new()];
+        // account for the `Name: ` in front of the value
+        let mut width = name.len() + 1;
+        for (i, (key, value)) in attributes.into_iter().enumerate() {
+            let separator = if i < last { ";" } else { "" };
+            let item = if key == "keydata" {
+                "keydata=".to_string()
+            } else {
+                format!("{}={}{}", key, value, separator)
+            };
+
+            if width + 1 + item.len() > LINE_WIDTH && !lines[lines.len() - 1].is_empty() {
+                lines.push(String::new());
+                width = 0;
+            }
+            let line = lines.last_mut().expect("lines is never empty");
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&item);
+            width += 1 + item.len();
+
+            if key == "keydata" {
+                // keep room for the separator on the last line
+                let chunk_size = LINE_WIDTH - 1 - separator.len();
+                for chunk in value.as_bytes().chunks(chunk_size) {
+                    lines.push(String::from_utf8_lossy(chunk).into_owned());
+                }
+                let line = lines.last_mut().expect("lines is never empty");
+                line.push_str(separator);
+                width = 1 + line.len();
+            }
+        }
+
+        lines.join("\r\n ")
+    }
+
+    /// Parse a header following the rules of the given spec version.
+    ///
+    /// `FromStr` uses `SpecVersion::Level1`, use `SpecVersion::Draft` to read
+    /// headers stored by earlier versions of this crate. Parsing is strict,
+    /// see `parse_with_options` for lenient parsing.
+    pub fn parse_with_spec(s: &str, spec: SpecVersion) -> Result<Header, HeaderParseError> {
+        Header::parse_with_options(s, &ParseOptions::strict().spec(spec)).map(|p| p.value)
+    }
+
+    /// Parse a header with the given options.
+    pub fn parse_with_options(s: &str,
+                              options: &ParseOptions)
+                              -> Result<Parsed<Header>, HeaderParseError> {
+        let parsed = HeaderRef::parse_with_options(s, options)?;
+        let header = parsed.value.to_header()?;
+
+        Ok(Parsed {
+               value: header,
+               warnings: parsed.warnings,
+           })
     }
 }
 
@@ -62,44 +375,522 @@ impl str::FromStr for Header {
     type Err = HeaderParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut attributes: BTreeMap<String, String> = s.split(";")
-            .filter_map(|a| {
-                            let attribute: Vec<&str> = a.trim().splitn(2, "=").collect();
-                            if attribute.len() < 2 {
-                                return None;
-                            }
-
-                            Some((attribute[0].to_string(), attribute[1].to_string()))
-                        })
-            .collect();
+        Header::parse_with_spec(s, SpecVersion::Level1)
+    }
+}
 
-        let addr = attributes
-            .remove("addr")
-            .ok_or(HeaderParseError::MissingCriticalAttribute("addr"))?;
-        let keydata = attributes
-            .remove("keydata")
-            .ok_or(HeaderParseError::MissingCriticalAttribute("keydata"))?;
+/// Undo RFC 5322 folding and strip the field name, if present.
+fn unfold(s: &str, name: &str) -> String {
+    let unfolded: String = s.chars().filter(|c| *c != '\r' && *c != '\n').collect();
+
+    match unfolded.find(':') {
+        Some(i) if unfolded[..i].trim().eq_ignore_ascii_case(name) => {
+            unfolded[i + 1..].trim().to_string()
+        }
+        _ => unfolded.trim().to_string(),
+    }
+}
 
-        let typ = attributes
-            .remove("type")
-            .and_then(|s| s.parse().ok())
-            .unwrap_or(KeyType::OpenPGP);
 
-        let pref = attributes
-            .remove("prefer-encrypt")
-            .and_then(|s| s.parse().ok())
-            .unwrap_or(EncryptPreference::None);
+/// An Autocrypt header borrowing from the string it was parsed from.
+///
+/// Parsing does not allocate, and `keydata` is only decoded on request. This
+/// makes it cheap to scan large amounts of headers, converting only those of
+/// interest to a `Header` with `to_header`, which applies the same rules as
+/// `Header::from_str`.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct HeaderRef<'a> {
+    /// The single recipient email address this header is valid for.
+    pub addr: &'a str,
+    /// Key type
+    pub typ: KeyType,
+    /// Encryption preference,
+    pub prefer_encrypt: EncryptPreference,
+    /// Public Key, Base64 encoded as found in the header, including any
+    /// folding whitespace.
+    pub keydata: &'a str,
+    /// The version of the spec this header is parsed with.
+    pub spec: SpecVersion,
+    /// The dialect this header is written in.
+    pub dialect: Dialect,
+    /// All attributes, in the order they appear.
+    attributes: Vec<Attribute<&'a str>>,
+    /// Byte offset of `addr` in the parsed string.
+    addr_offset: usize,
+    /// Byte offset of `keydata` in the parsed string.
+    keydata_offset: usize,
+}
+
+impl<'a> HeaderRef<'a> {
+    /// Parse a header following the rules of Autocrypt Level 1.
+    pub fn parse(s: &'a str) -> Result<HeaderRef<'a>, HeaderParseError> {
+        HeaderRef::parse_with_spec(s, SpecVersion::Level1)
+    }
 
-        // Ensure no unkown critical attributes are present
-        let crit_count = attributes
-            .keys()
-            .filter(|k| !k.starts_with("_"))
-            .count();
-        if crit_count > 0 {
-            return Err(HeaderParseError::UnknownCriticalAttributes);
+    /// Parse a header following the rules of the given spec version.
+    pub fn parse_with_spec(s: &'a str,
+                           spec: SpecVersion)
+                           -> Result<HeaderRef<'a>, HeaderParseError> {
+        HeaderRef::parse_with_options(s, &ParseOptions::strict().spec(spec)).map(|p| p.value)
+    }
+
+    /// Parse a header with the given options.
+    ///
+    /// When parsing strictly the first warning is returned as
+    /// `HeaderParseError::Rejected`. The `limits` of the options are
+    /// enforced before any other work is done on the offending part.
+    pub fn parse_with_options(s: &'a str,
+                              options: &ParseOptions)
+                              -> Result<Parsed<HeaderRef<'a>>, HeaderParseError> {
+        let limits = &options.limits;
+        if s.len() > limits.max_header_size {
+            return Err(HeaderParseError::HeaderTooLarge {
+                           size: s.len(),
+                           limit: limits.max_header_size,
+                       });
+        }
+
+        let mut warnings = Warnings::new(options.policy);
+        let mut addr = None;
+        let mut keydata = None;
+        let mut typ = None;
+        let mut pref = None;
+        let mut unknown_critical = None;
+        let mut legacy_to = false;
+        let mut count = 0;
+        let mut attributes: Vec<Attribute<&str>> = Vec::new();
+
+        for attribute in s.split(';') {
+            let attribute = attribute.trim();
+            let offset = offset_in(s, attribute);
+            if attribute.is_empty() {
+                warnings.warn(ParseWarning::EmptyAttribute, offset)?;
+                continue;
+            }
+
+            count += 1;
+            if count > limits.max_attributes {
+                return Err(HeaderParseError::TooManyAttributes {
+                               limit: limits.max_attributes,
+                               offset,
+                           });
+            }
+            let name = attribute.split('=').next().unwrap_or(attribute);
+            if attribute.len() > limits.max_attribute_length && name != "keydata" {
+                return Err(HeaderParseError::AttributeTooLong {
+                               name: name.to_string(),
+                               length: attribute.len(),
+                               limit: limits.max_attribute_length,
+                               offset,
+                           });
+            }
+
+            let mut parts = attribute.splitn(2, '=');
+            let (key, value) = match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) => (key, value),
+                _ => {
+                    warnings.warn(ParseWarning::MalformedAttribute(attribute.to_string()),
+                                  offset)?;
+                    continue;
+                }
+            };
+
+            let (previous, attribute) = match key {
+                "addr" => (addr.replace(value), Attribute::Addr),
+                "to" if options.legacy => {
+                    legacy_to = true;
+                    (addr.replace(value), Attribute::Addr)
+                }
+                "keydata" => (keydata.replace(value), Attribute::Keydata),
+                "prefer-encrypt" => (pref.replace(value), Attribute::PreferEncrypt),
+                // Level 1 dropped the `type` attribute, so there it is
+                // rejected as an unknown critical attribute, unless it is one
+                // of the OpenPGP values of legacy headers.
+                "type" if options.spec == SpecVersion::Draft ||
+                          options.legacy && (value == "p" || value == "1") => {
+                    (typ.replace(value), Attribute::Type)
+                }
+                // non-critical attributes may repeat, all values are kept
+                _ if key.starts_with('_') => (None, Attribute::Other(key, value)),
+                _ => {
+                    unknown_critical.get_or_insert((key, offset));
+                    continue;
+                }
+            };
+            // critical attributes keep the position of their first occurrence
+            if previous.is_none() || !attribute_is_critical(&attribute) {
+                attributes.push(attribute);
+            }
+            if let Some(ignored) = previous {
+                warnings.warn(ParseWarning::DuplicateAttribute {
+                                  name: key.to_string(),
+                                  ignored: ignored.to_string(),
+                              },
+                              offset)?;
+            }
         }
 
-        return Ok(Header::new(addr.to_string(), typ, pref, keydata.to_string(), attributes));
+        let addr = addr.ok_or(HeaderParseError::MissingCriticalAttribute("addr"))?;
+        let keydata = keydata.ok_or(HeaderParseError::MissingCriticalAttribute("keydata"))?;
+        if let Some((name, offset)) = unknown_critical {
+            return Err(HeaderParseError::UnknownCriticalAttribute {
+                           name: name.to_string(),
+                           offset,
+                       });
+        }
+        let addr_offset = offset_in(s, addr);
+        let keydata_offset = offset_in(s, keydata);
+        let key_size = decoded_size(keydata);
+        if key_size > limits.max_key_size {
+            return Err(HeaderParseError::KeyTooLarge {
+                           size: key_size,
+                           limit: limits.max_key_size,
+                           offset: keydata_offset,
+                       });
+        }
+        if let Some(i) = stray_whitespace(keydata) {
+            warnings.warn(ParseWarning::KeydataWhitespace, keydata_offset + i)?;
+        }
+        let legacy_type = options.legacy && typ == Some("p");
+        if legacy_type {
+            typ = Some("1");
+        }
+
+        Ok(Parsed {
+               value: HeaderRef {
+                   addr,
+                   typ: typ.and_then(|s| s.parse().ok()).unwrap_or(KeyType::OpenPGP),
+                   prefer_encrypt: pref.and_then(|s| s.parse().ok())
+                       .unwrap_or(EncryptPreference::None),
+                   keydata,
+                   spec: options.spec,
+                   dialect: Dialect::detect(legacy_to, legacy_type),
+                   attributes,
+                   addr_offset,
+                   keydata_offset,
+               },
+               warnings: warnings.into_vec(),
+           })
+    }
+
+    /// Get any additional attributes
+    ///
+    /// If an attribute is present more than once, the last value is returned.
+    pub fn get(&self, key: &str) -> Option<&'a str> {
+        last_value(&self.attributes, key)
+    }
+
+    /// Decode the Base64 encoded `keydata`.
+    pub fn decode_keydata(&self) -> Result<KeyData, KeyDataParseError> {
+        self.keydata.parse()
+    }
+
+    /// Parse `addr` as an `EmailAddress`.
+    pub fn parse_addr(&self) -> Result<EmailAddress, HeaderParseError> {
+        EmailAddress::parse(self.addr).map_err(|err| {
+                                                   HeaderParseError::InvalidAddress {
+                                                       err,
+                                                       offset: self.addr_offset,
+                                                   }
+                                               })
+    }
+
+    /// Convert into an owned `Header`, parsing `addr` and decoding `keydata`.
+    pub fn to_header(&self) -> Result<Header, HeaderParseError> {
+        let addr = self.parse_addr()?;
+        let keydata = self.decode_keydata()
+            .map_err(|err| {
+                         HeaderParseError::InvalidKeyData {
+                             err,
+                             offset: self.keydata_offset,
+                         }
+                     })?;
+
+        Ok(Header {
+               addr,
+               typ: self.typ.clone(),
+               prefer_encrypt: self.prefer_encrypt.clone(),
+               keydata,
+               spec: self.spec,
+               dialect: self.dialect,
+               attributes: self.attributes.iter().map(|a| a.into_owned()).collect(),
+           })
+    }
+}
+
+
+/// Collects warnings, or turns them into errors when parsing strictly.
+struct Warnings {
+    policy: ParsePolicy,
+    warnings: Vec<ParseWarning>,
+}
+
+impl Warnings {
+    fn new(policy: ParsePolicy) -> Warnings {
+        Warnings {
+            policy,
+            warnings: Vec::new(),
+        }
+    }
+
+    fn warn(&mut self, warning: ParseWarning, offset: usize) -> Result<(), HeaderParseError> {
+        match self.policy {
+            ParsePolicy::Strict => Err(HeaderParseError::Rejected { warning, offset }),
+            ParsePolicy::Lenient => {
+                self.warnings.push(warning);
+                Ok(())
+            }
+        }
+    }
+
+    fn into_vec(self) -> Vec<ParseWarning> {
+        self.warnings
+    }
+}
+
+/// The last value of the non default attribute `key`.
+fn last_value<'a>(attributes: &[Attribute<&'a str>], key: &str) -> Option<&'a str> {
+    attributes
+        .iter()
+        .rev()
+        .filter_map(|a| match *a {
+                        Attribute::Other(k, v) if k == key => Some(v),
+                        _ => None,
+                    })
+        .next()
+}
+
+fn attribute_is_critical<S>(attribute: &Attribute<S>) -> bool {
+    !matches!(*attribute, Attribute::Other(..))
+}
+
+/// The byte offset of `part`, a slice of `s`, in `s`.
+fn offset_in(s: &str, part: &str) -> usize {
+    part.as_ptr() as usize - s.as_ptr() as usize
+}
+
+/// The size of Base64 encoded data once decoded, without decoding it.
+fn decoded_size(base64: &str) -> usize {
+    let mut len: usize = 0;
+    let mut padding = 0;
+    for c in base64.chars().filter(|c| !c.is_ascii_whitespace()) {
+        len += 1;
+        padding = if c == '=' { padding + 1 } else { 0 };
+    }
+
+    (len * 3 / 4).saturating_sub(padding)
+}
+
+/// Find the first whitespace in `s` that is neither a space or tab, nor a
+/// line break followed by one, as left by folding.
+fn stray_whitespace(s: &str) -> Option<usize> {
+    let mut chars = s.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let line_break = match c {
+            '\r' => chars.next().map(|(_, c)| c) == Some('\n'),
+            '\n' => true,
+            ' ' | '\t' => continue,
+            _ if c.is_whitespace() => return Some(i),
+            _ => continue,
+        };
+        if !line_break || !matches!(chars.peek(), Some(&(_, ' ')) | Some(&(_, '\t'))) {
+            return Some(i);
+        }
+    }
+
+    None
+}
+
+
+/// Builder for `Header` values, validating them on `build`.
+///
+/// Unlike `Header::new`, this makes sure that only non-critical attributes,
+/// starting with `_`, are added and that `addr` is an email address.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct HeaderBuilder {
+    addr: Option<String>,
+    prefer_encrypt: EncryptPreference,
+    keydata: Option<KeyData>,
+    spec: SpecVersion,
+    attributes: Vec<(String, String)>,
+}
+
+impl HeaderBuilder {
+    pub fn new() -> HeaderBuilder {
+        HeaderBuilder::default()
+    }
+
+    /// Set the address the header is valid for, required.
+    pub fn addr<S: Into<String>>(mut self, addr: S) -> HeaderBuilder {
+        self.addr = Some(addr.into());
+        self
+    }
+
+    /// Set the encryption preference, defaults to `EncryptPreference::None`.
+    pub fn prefer_encrypt(mut self, pref: EncryptPreference) -> HeaderBuilder {
+        self.prefer_encrypt = pref;
+        self
+    }
+
+    /// Set the public key, required.
+    pub fn keydata(mut self, keydata: KeyData) -> HeaderBuilder {
+        self.keydata = Some(keydata);
+        self
+    }
+
+    /// Set the spec version used to serialize the header.
+    pub fn spec(mut self, spec: SpecVersion) -> HeaderBuilder {
+        self.spec = spec;
+        self
+    }
+
+    /// Add a non-critical attribute, replacing any previous value.
+    ///
+    /// Attributes are serialized in the order they are first added. The name
+    /// must start with `_`, this is checked by `build`.
+    pub fn attribute<K, V>(mut self, key: K, value: V) -> HeaderBuilder
+        where K: Into<String>,
+              V: Into<String>
+    {
+        let (key, value) = (key.into(), value.into());
+        match self.attributes.iter_mut().find(|&&mut (ref k, _)| *k == key) {
+            Some(attribute) => attribute.1 = value,
+            None => self.attributes.push((key, value)),
+        }
+        self
+    }
+
+    /// Remove a previously added attribute.
+    pub fn remove_attribute<K: Into<String>>(mut self, key: K) -> HeaderBuilder {
+        let key = key.into();
+        self.attributes.retain(|(k, _)| *k != key);
+        self
+    }
+
+    /// Validate the attributes and build the header.
+    ///
+    /// This does not validate the key itself, use `Header::validate` for that.
+    pub fn build(self) -> Result<Header, HeaderBuildError> {
+        let addr = self.addr
+            .ok_or(HeaderBuildError::MissingAttribute("addr"))?;
+        // quoted local parts may contain characters breaking the header
+        let addr = match EmailAddress::parse(&addr) {
+            Ok(parsed) if is_attribute_text(&addr, false) => parsed,
+            _ => return Err(HeaderBuildError::InvalidAddress(addr)),
+        };
+        let keydata = self.keydata
+            .ok_or(HeaderBuildError::MissingAttribute("keydata"))?;
+
+        let mut header = Header::new(addr,
+                                     KeyType::OpenPGP,
+                                     self.prefer_encrypt,
+                                     keydata,
+                                     self.attributes)?;
+        header.spec = self.spec;
+
+        Ok(header)
+    }
+}
+
+/// Check that a non-critical attribute can be added to a header.
+pub(crate) fn check_attribute(key: &str, value: &str) -> Result<(), HeaderBuildError> {
+    if !key.starts_with('_') {
+        return Err(HeaderBuildError::CriticalAttribute(key.to_string()));
+    }
+    if key.len() < 2 || !is_attribute_text(key, false) || !is_attribute_text(value, true) ||
+       value.trim() != value {
+        return Err(HeaderBuildError::InvalidAttribute(key.to_string()));
+    }
+    // folded on a line of its own: a space, `key=value` and the separator
+    if key.len() + value.len() + 3 > MAX_LINE_LENGTH {
+        return Err(HeaderBuildError::AttributeTooLong(key.to_string()));
+    }
+
+    Ok(())
+}
+
+/// Check that `s` can be used as an attribute name, or value, without
+/// breaking the header grammar.
+fn is_attribute_text(s: &str, value: bool) -> bool {
+    s.chars()
+        .all(|c| {
+                 !c.is_control() && c != ';' &&
+                 (value || (c != '=' && !c.is_whitespace()))
+             })
+}
+
+
+/// Represents an Autocrypt-Gossip Header
+///
+/// Gossip headers share the attribute grammar of `Header`, but never carry a
+/// `prefer-encrypt` attribute, as the preference of a third party is unknown.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct GossipHeader {
+    header: Header,
+}
+
+impl GossipHeader {
+    pub fn new(addr: EmailAddress, keydata: KeyData) -> GossipHeader {
+        Header::new_unchecked(addr,
+                              KeyType::OpenPGP,
+                              EncryptPreference::None,
+                              keydata,
+                              Vec::new())
+                .into()
+    }
+
+    /// The email address of the gossiped peer.
+    pub fn addr(&self) -> &EmailAddress {
+        &self.header.addr
+    }
+
+    /// Public key of the gossiped peer.
+    pub fn keydata(&self) -> &KeyData {
+        &self.header.keydata
+    }
+
+    /// The underlying header.
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn into_header(self) -> Header {
+        self.header
+    }
+
+    /// Serialize as a complete, folded `Autocrypt-Gossip:` header field.
+    ///
+    /// See `Header::to_folded`.
+    pub fn to_folded(&self) -> String {
+        format!("Autocrypt-Gossip: {}", self.header.folded_value("Autocrypt-Gossip"))
+    }
+
+    /// Parse a header field as produced by `to_folded`, accepting any folding.
+    pub fn from_folded(s: &str) -> Result<GossipHeader, HeaderParseError> {
+        unfold(s, "Autocrypt-Gossip").parse()
+    }
+}
+
+impl From<Header> for GossipHeader {
+    fn from(mut header: Header) -> GossipHeader {
+        header.prefer_encrypt = EncryptPreference::None;
+        header.spec = SpecVersion::Level1;
+
+        GossipHeader { header }
+    }
+}
+
+impl fmt::Display for GossipHeader {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        self.header.fmt(fmt)
+    }
+}
+
+impl str::FromStr for GossipHeader {
+    type Err = HeaderParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse::<Header>().map(GossipHeader::from)
     }
 }
 
@@ -107,80 +898,684 @@ impl str::FromStr for Header {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use errors::{AddressParseError, KeyDataParseError};
+    use options::Limits;
 
-    fn keydata() -> String {
-        "mDMEWFUX7RYJKwYBBAHaRw8BAQdACHq6FkRGsHqBMsNpD7d+Q2jtxVwTO+Y4NhBaQyHaMj+0HWFsaWNlQHRlc3RzdWl0ZS5hdXRvY3J5cHQub3JniJAEExYIADgWIQQmqmdR/XZoxC+kkkr8dE2p/nPD1AUCWFUX7QIbAwULCQgHAgYVCAkKCwIEFgIDAQIeAQIXgAAKCRD8dE2p/nPD1EqOAP0WUDKwko001X7XTSYbWGWmXfR9P1Aw6917EnkVQMsp3gEA86Ii8ArL3jd+E2qS5JSysx/qiVhuTSwWzmC5K6zKdg+4OARYVRfuEgorBgEEAZdVAQUBAQdAv1A88FoCfwz0zSh6NNnUuKuz1p3ctJ3kXMGotsVYjA0DAQgHiHgEGBYIACAWIQQmqmdR/XZoxC+kkkr8dE2p/nPD1AUCWFUX7gIbDAAKCRD8dE2p/nPD1FTOAP4nS14sX7a/nBXBKWAh/oX8iVtkhmZqjy9tG21BcNqb+wEAq73H4+1ncnkscR3Nu4GYzNRSD3NXq68tEESK28kYvw4=".to_string()
+    fn keydata() -> KeyData {
+        "mDMEWFUX7RYJKwYBBAHaRw8BAQdACHq6FkRGsHqBMsNpD7d+Q2jtxVwTO+Y4NhBaQyHaMj+0HWFsaWNlQHRlc3RzdWl0ZS5hdXRvY3J5cHQub3JniJAEExYIADgWIQQmqmdR/XZoxC+kkkr8dE2p/nPD1AUCWFUX7QIbAwULCQgHAgYVCAkKCwIEFgIDAQIeAQIXgAAKCRD8dE2p/nPD1EqOAP0WUDKwko001X7XTSYbWGWmXfR9P1Aw6917EnkVQMsp3gEA86Ii8ArL3jd+E2qS5JSysx/qiVhuTSwWzmC5K6zKdg+4OARYVRfuEgorBgEEAZdVAQUBAQdAv1A88FoCfwz0zSh6NNnUuKuz1p3ctJ3kXMGotsVYjA0DAQgHiHgEGBYIACAWIQQmqmdR/XZoxC+kkkr8dE2p/nPD1AUCWFUX7gIbDAAKCRD8dE2p/nPD1FTOAP4nS14sX7a/nBXBKWAh/oX8iVtkhmZqjy9tG21BcNqb+wEAq73H4+1ncnkscR3Nu4GYzNRSD3NXq68tEESK28kYvw4=".parse().unwrap()
+    }
+
+    fn mykey() -> KeyData {
+        KeyData::new(b"mykey".to_vec())
     }
 
     #[test]
     fn test_to_string() {
-        let h = Header::new("me@mail.com".to_string(),
+        let h = Header::new("me@mail.com".parse().unwrap(),
                             KeyType::OpenPGP,
                             EncryptPreference::Mutual,
                             keydata(),
-                            BTreeMap::new());
+                            Vec::new()).unwrap();
 
         assert_eq!(h.to_string(),
-                   format!("addr=me@mail.com; type=1; prefer-encrypt=mutual; keydata={}",
+                   format!("addr=me@mail.com; prefer-encrypt=mutual; keydata={}",
+                           keydata()));
+    }
+
+    #[test]
+    fn test_to_string_draft() {
+        let mut h = Header::new("me@mail.com".parse().unwrap(),
+                                KeyType::OpenPGP,
+                                EncryptPreference::None,
+                                keydata(),
+                                Vec::new()).unwrap();
+        h.spec = SpecVersion::Draft;
+
+        assert_eq!(h.to_string(),
+                   format!("addr=me@mail.com; type=1; prefer-encrypt=nopreference; keydata={}",
                            keydata()));
     }
 
     #[test]
     fn test_from_str() {
-        let h: Header = "addr=me@mail.com; type=1; prefer-encrypt=mutual; keydata=mykey"
+        let h: Header = "addr=me@mail.com; prefer-encrypt=mutual; keydata=bXlrZXk="
             .parse()
             .expect("failed to parse");
 
         assert_eq!(h.addr, "me@mail.com");
         assert_eq!(h.typ, KeyType::OpenPGP);
         assert_eq!(h.prefer_encrypt, EncryptPreference::Mutual);
-        assert_eq!(h.keydata, "mykey");
+        assert_eq!(h.keydata, mykey());
+        assert_eq!(h.spec, SpecVersion::Level1);
+    }
+
+    #[test]
+    fn test_from_str_level1_rejects_type() {
+        let raw = "addr=me@mail.com; type=1; prefer-encrypt=mutual; keydata=bXlrZXk=";
+        assert_eq!(raw.parse::<Header>().err(),
+                   Some(HeaderParseError::UnknownCriticalAttribute {
+                            name: "type".to_string(),
+                            offset: 18,
+                        }));
+    }
+
+    #[test]
+    fn test_from_str_level1_unknown_preference() {
+        let h: Header = "addr=me@mail.com; prefer-encrypt=sometimes; keydata=bXlrZXk="
+            .parse()
+            .expect("failed to parse");
+
+        assert_eq!(h.prefer_encrypt, EncryptPreference::None);
+        assert_eq!(h.to_string(), "addr=me@mail.com; keydata=bXlrZXk=");
+    }
+
+    #[test]
+    fn test_from_str_draft() {
+        let raw = "addr=me@mail.com; type=1; prefer-encrypt=nopreference; _foo=one; keydata=bXlrZXk=";
+        let h = Header::parse_with_spec(raw, SpecVersion::Draft).expect("failed to parse");
+
+        assert_eq!(h.addr, "me@mail.com");
+        assert_eq!(h.typ, KeyType::OpenPGP);
+        assert_eq!(h.prefer_encrypt, EncryptPreference::None);
+        assert_eq!(h.keydata, mykey());
+        assert_eq!(h.spec, SpecVersion::Draft);
+        assert_eq!(h.to_string(), raw);
     }
 
     #[test]
     fn test_from_str_minimal() {
-        let h: Header = "addr=me@mail.com; keydata=mykey"
+        let h: Header = "addr=me@mail.com; keydata=bXlrZXk="
             .parse()
             .expect("failed to parse");
 
         assert_eq!(h.addr, "me@mail.com");
         assert_eq!(h.typ, KeyType::OpenPGP);
         assert_eq!(h.prefer_encrypt, EncryptPreference::None);
-        assert_eq!(h.keydata, "mykey");
+        assert_eq!(h.keydata, mykey());
     }
 
     #[test]
     fn test_from_str_non_critical() {
-        let raw = "addr=me@mail.com; _foo=one; _bar=two; keydata=mykey";
+        let raw = "addr=me@mail.com; _foo=one; _bar=two; keydata=bXlrZXk=";
         let h: Header = raw.parse().expect("failed to parse");
 
         assert_eq!(h.addr, "me@mail.com");
         assert_eq!(h.typ, KeyType::OpenPGP);
         assert_eq!(h.prefer_encrypt, EncryptPreference::None);
-        assert_eq!(h.keydata, "mykey");
+        assert_eq!(h.keydata, mykey());
         assert_eq!(h.get("_foo").unwrap(), "one");
         assert_eq!(h.get("_bar").unwrap(), "two");
 
         assert_eq!(h.to_string(),
-                   "addr=me@mail.com; type=1; prefer-encrypt=nopreference; _bar=two; _foo=one; keydata=mykey");
+                   "addr=me@mail.com; _foo=one; _bar=two; keydata=bXlrZXk=");
+    }
+
+    #[test]
+    fn test_roundtrip_order_and_duplicates() {
+        let raw = "_x=1; addr=me@mail.com; _foo=1; prefer-encrypt=mutual; keydata=bXlrZXk=; _foo=2";
+        let h: Header = raw.parse().expect("failed to parse");
+
+        assert_eq!(h.get("_foo").unwrap(), "2");
+        assert_eq!(h.to_string(), raw);
+
+        let attributes: Vec<_> = h.attributes().map(|(k, v)| format!("{}={}", k, v)).collect();
+        assert_eq!(attributes,
+                   vec!["_x=1", "addr=me@mail.com", "_foo=1", "prefer-encrypt=mutual",
+                        "keydata=bXlrZXk=", "_foo=2"]);
+
+        let folded = h.to_folded();
+        assert!(folded.ends_with("keydata=\r\n bXlrZXk=; _foo=2"));
+        let unfolded = Header::from_folded(&folded).unwrap();
+        assert_eq!(unfolded.to_string(), raw);
+    }
+
+    #[test]
+    fn test_attributes_omitted_critical() {
+        let h = Header::new("me@mail.com".parse().unwrap(),
+                            KeyType::OpenPGP,
+                            EncryptPreference::Mutual,
+                            mykey(),
+                            vec![("_foo".to_string(), "one".to_string())]).unwrap();
+        let names: Vec<_> = h.attributes().map(|(k, _)| k).collect();
+        assert_eq!(names, vec!["addr", "prefer-encrypt", "_foo", "keydata"]);
+
+        let mut draft = h.clone();
+        draft.spec = SpecVersion::Draft;
+        let names: Vec<_> = draft.attributes().map(|(k, _)| k).collect();
+        assert_eq!(names, vec!["addr", "type", "prefer-encrypt", "_foo", "keydata"]);
     }
 
     #[test]
     fn test_from_str_superflous_critical() {
-        let raw = "addr=me@mail.com; _foo=one; _bar=two; other=me; keydata=mykey";
+        let raw = "addr=me@mail.com; _foo=one; _bar=two; other=me; keydata=bXlrZXk=";
         assert_eq!(raw.parse::<Header>().err(),
-                   Some(HeaderParseError::UnknownCriticalAttributes));
+                   Some(HeaderParseError::UnknownCriticalAttribute {
+                            name: "other".to_string(),
+                            offset: 38,
+                        }));
     }
 
     #[test]
     fn test_from_str_special() {
-        let h: Header = "addr=me@mail.com; type=1; prefer-encrypt=mutual; keydata=my=key="
+        let h: Header = "addr=me@mail.com; prefer-encrypt=mutual; keydata=bQ=="
             .parse()
             .expect("failed to parse");
 
         assert_eq!(h.addr, "me@mail.com");
         assert_eq!(h.typ, KeyType::OpenPGP);
         assert_eq!(h.prefer_encrypt, EncryptPreference::Mutual);
-        assert_eq!(h.keydata, "my=key=");
+        assert_eq!(h.keydata.as_bytes(), b"m");
+    }
+
+    #[test]
+    fn test_from_str_folded_keydata() {
+        let h: Header = "addr=me@mail.com; keydata=\r\n bXlr\r\n ZXk="
+            .parse()
+            .expect("failed to parse");
+
+        assert_eq!(h.keydata, mykey());
+        assert_eq!(h.to_string(), "addr=me@mail.com; keydata=bXlrZXk=");
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let h = Header::new("alice@testsuite.autocrypt.org".parse().unwrap(),
+                            KeyType::OpenPGP,
+                            EncryptPreference::Mutual,
+                            keydata(),
+                            Vec::new()).unwrap();
+
+        assert_eq!(h.fingerprint().unwrap().to_hex(),
+                   "26AA6751FD7668C42FA4924AFC744DA9FE73C3D4");
+        assert_eq!(h.key_id().unwrap().to_hex(), "FC744DA9FE73C3D4");
+        assert_eq!(h.subkey_fingerprints().unwrap()[0].key_id().to_hex(),
+                   "C844E3CB6CE55B79");
+
+        let h = Header::new("me@mail.com".parse().unwrap(),
+                            KeyType::OpenPGP,
+                            EncryptPreference::Mutual,
+                            mykey(),
+                            Vec::new()).unwrap();
+        assert!(h.fingerprint().is_err());
+    }
+
+    #[test]
+    fn test_from_str_invalid_keydata() {
+        let raw = "addr=me@mail.com; keydata=my*key";
+        match raw.parse::<Header>() {
+            Err(HeaderParseError::InvalidKeyData { offset: 26, .. }) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        assert_eq!("addr=me@mail.com; keydata=".parse::<Header>().err(),
+                   Some(HeaderParseError::InvalidKeyData {
+                            err: KeyDataParseError::Empty,
+                            offset: 26,
+                        }));
+    }
+
+    #[test]
+    fn test_to_folded() {
+        let h = Header::new("alice@testsuite.autocrypt.org".parse().unwrap(),
+                            KeyType::OpenPGP,
+                            EncryptPreference::Mutual,
+                            keydata(),
+                            Vec::new()).unwrap();
+        let folded = h.to_folded();
+
+        let lines: Vec<&str> = folded.split("\r\n").collect();
+        assert_eq!(lines[0],
+                   "Autocrypt: addr=alice@testsuite.autocrypt.org; prefer-encrypt=mutual;");
+        assert_eq!(lines[1], " keydata=");
+        assert_eq!(lines[2], format!(" {}", &keydata().to_base64()[..75]));
+        for line in &lines[1..] {
+            assert!(line.starts_with(' '));
+            assert!(line.len() <= 76);
+        }
+        assert_eq!(lines[2..].concat().replace(' ', ""), keydata().to_base64());
+        assert!(!folded.ends_with("\r\n"));
+    }
+
+    #[test]
+    fn test_to_folded_long_attributes() {
+        let attributes = vec![("_comment".to_string(), "x".repeat(30)),
+                              ("_other".to_string(), "y".repeat(40))];
+        let h = Header::new("me@mail.com".parse().unwrap(),
+                            KeyType::OpenPGP,
+                            EncryptPreference::None,
+                            mykey(),
+                            attributes).unwrap();
+
+        assert_eq!(h.to_folded(),
+                   format!("Autocrypt: addr=me@mail.com; _comment={};\r\n _other={}; \
+                            keydata=\r\n bXlrZXk=",
+                           "x".repeat(30),
+                           "y".repeat(40)));
+    }
+
+    #[test]
+    fn test_folded_roundtrip() {
+        let h = Header::new("me@mail.com".parse().unwrap(),
+                            KeyType::OpenPGP,
+                            EncryptPreference::Mutual,
+                            keydata(),
+                            Vec::new()).unwrap();
+
+        assert_eq!(Header::from_folded(&h.to_folded()).expect("failed to parse"), h);
+    }
+
+    #[test]
+    fn test_from_folded_variants() {
+        let variants = ["Autocrypt: addr=me@mail.com;\r\n prefer-encrypt=mutual; keydata=\r\n bXlr\r\n ZXk=\r\n",
+                        "autocrypt:addr=me@mail.com;\n\tprefer-encrypt=mutual;\n keydata=bXlr\n  ZXk=",
+                        "AUTOCRYPT : addr=me@mail.com; prefer-encrypt=mutual;\r\tkeydata=\r bXlrZXk=",
+                        "addr=me@mail.com; prefer-encrypt=mutual; keydata=bXlrZXk="];
+
+        for raw in variants.iter() {
+            let h = Header::from_folded(raw).expect("failed to parse");
+            assert_eq!(h.addr, "me@mail.com");
+            assert_eq!(h.prefer_encrypt, EncryptPreference::Mutual);
+            assert_eq!(h.keydata, mykey());
+        }
+    }
+
+    #[test]
+    fn test_header_ref() {
+        let raw = "addr=me@mail.com; prefer-encrypt=mutual; _foo=one; _bar=two; keydata=\r\n bXlr\r\n ZXk=";
+        let h = HeaderRef::parse(raw).expect("failed to parse");
+
+        assert_eq!(h.addr, "me@mail.com");
+        assert_eq!(h.prefer_encrypt, EncryptPreference::Mutual);
+        assert_eq!(h.keydata, "\r\n bXlr\r\n ZXk=");
+        assert_eq!(h.get("_foo"), Some("one"));
+        assert_eq!(h.get("_baz"), None);
+
+        // borrowed from the input
+        let range = raw.as_ptr() as usize..raw.as_ptr() as usize + raw.len();
+        assert!(range.contains(&(h.addr.as_ptr() as usize)));
+        assert!(range.contains(&(h.keydata.as_ptr() as usize)));
+
+        assert_eq!(h.decode_keydata().unwrap(), mykey());
+        assert_eq!(h.to_header().unwrap(), raw.parse::<Header>().unwrap());
+    }
+
+    #[test]
+    fn test_addr_normalized() {
+        let h: Header = "addr=Me@Mail.COM; keydata=bXlrZXk=".parse().expect("failed to parse");
+
+        assert_eq!(h.addr, "me@mail.com");
+        assert_eq!(h.addr.domain(), "mail.com");
+        assert_eq!(h.to_string(), "addr=Me@Mail.COM; keydata=bXlrZXk=");
+
+        let h: Header = "addr=me@bücher.example; keydata=bXlrZXk=".parse().expect("failed to parse");
+        assert_eq!(h.addr, "me@xn--bcher-kva.example");
+    }
+
+    #[test]
+    fn test_invalid_addr() {
+        let raw = "_foo=1; addr=me@mail..com; keydata=bXlrZXk=";
+        let h = HeaderRef::parse(raw).expect("failed to parse");
+        assert_eq!(h.addr, "me@mail..com");
+
+        let err = h.to_header().unwrap_err();
+        assert_eq!(err,
+                   HeaderParseError::InvalidAddress {
+                       err: AddressParseError::InvalidDomain("mail..com".to_string()),
+                       offset: 13,
+                   });
+        assert_eq!(raw.parse::<Header>().unwrap_err(), err);
+        assert_eq!(err.attribute(), Some("addr"));
+    }
+
+    #[test]
+    fn test_header_ref_errors() {
+        assert_eq!(HeaderRef::parse("keydata=bXlrZXk=").err(),
+                   Some(HeaderParseError::MissingCriticalAttribute("addr")));
+        assert_eq!(HeaderRef::parse("addr=me@mail.com").err(),
+                   Some(HeaderParseError::MissingCriticalAttribute("keydata")));
+        assert_eq!(HeaderRef::parse("addr=me@mail.com; type=1; keydata=bXlrZXk=").err(),
+                   Some(HeaderParseError::UnknownCriticalAttribute {
+                            name: "type".to_string(),
+                            offset: 18,
+                        }));
+
+        // keydata is only decoded when converting
+        let h = HeaderRef::parse("addr=me@mail.com; keydata=my*key").expect("failed to parse");
+        match h.to_header() {
+            Err(HeaderParseError::InvalidKeyData { .. }) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_header_ref_draft() {
+        let raw = "addr=me@mail.com; type=1; prefer-encrypt=nopreference; keydata=bXlrZXk=";
+        let h = HeaderRef::parse_with_spec(raw, SpecVersion::Draft).expect("failed to parse");
+
+        assert_eq!(h.typ, KeyType::OpenPGP);
+        assert_eq!(h.spec, SpecVersion::Draft);
+        assert_eq!(h.to_header().unwrap().to_string(), raw);
+    }
+
+    #[test]
+    fn test_parse_legacy() {
+        let legacy = ParseOptions::strict().legacy(true);
+        let cases = [("to=me@mail.com; type=p; keydata=bXlrZXk=", Dialect::LegacyToAndType),
+                     ("to=me@mail.com; keydata=bXlrZXk=", Dialect::LegacyTo),
+                     ("addr=me@mail.com; type=p; keydata=bXlrZXk=", Dialect::LegacyType),
+                     ("addr=me@mail.com; type=1; keydata=bXlrZXk=", Dialect::Standard),
+                     ("addr=me@mail.com; keydata=bXlrZXk=", Dialect::Standard)];
+
+        for &(raw, dialect) in cases.iter() {
+            let h = Header::parse_with_options(raw, &legacy).expect("failed to parse").value;
+            assert_eq!(h.dialect, dialect);
+            assert_eq!(h.addr, "me@mail.com");
+            assert_eq!(h.typ, KeyType::OpenPGP);
+            // serialized as a current header
+            assert_eq!(h.to_string(), "addr=me@mail.com; keydata=bXlrZXk=");
+            assert_eq!(h, "addr=me@mail.com; keydata=bXlrZXk=".parse().unwrap());
+        }
+
+        assert_eq!(Header::parse_with_options("to=me@mail.com; type=x; keydata=bXlrZXk=", &legacy)
+                       .err(),
+                   Some(HeaderParseError::UnknownCriticalAttribute {
+                            name: "type".to_string(),
+                            offset: 16,
+                        }));
+
+        let h = HeaderRef::parse_with_options("to=me@mail.com; type=p; keydata=bXlrZXk=",
+                                              &legacy.spec(SpecVersion::Draft))
+            .expect("failed to parse")
+            .value;
+        assert_eq!(h.dialect, Dialect::LegacyToAndType);
+        assert_eq!(h.to_header().unwrap().to_string(),
+                   "addr=me@mail.com; prefer-encrypt=nopreference; type=1; keydata=bXlrZXk=");
+    }
+
+    #[test]
+    fn test_parse_legacy_disabled() {
+        assert_eq!("to=me@mail.com; keydata=bXlrZXk=".parse::<Header>().err(),
+                   Some(HeaderParseError::MissingCriticalAttribute("addr")));
+        assert_eq!("addr=me@mail.com; type=p; keydata=bXlrZXk=".parse::<Header>().err(),
+                   Some(HeaderParseError::UnknownCriticalAttribute {
+                            name: "type".to_string(),
+                            offset: 18,
+                        }));
+
+        let h = Header::parse_with_spec("addr=me@mail.com; type=p; keydata=bXlrZXk=",
+                                        SpecVersion::Draft)
+            .expect("failed to parse");
+        assert_eq!(h.dialect, Dialect::Standard);
+        assert_eq!(h.typ, KeyType::Unknown("p".to_string()));
+
+        // both forms of the address are duplicates
+        let raw = "to=other@mail.com; addr=me@mail.com; keydata=bXlrZXk=";
+        assert_eq!(Header::parse_with_options(raw, &ParseOptions::strict().legacy(true)).err(),
+                   Some(HeaderParseError::Rejected {
+                            warning: ParseWarning::DuplicateAttribute {
+                                name: "addr".to_string(),
+                                ignored: "other@mail.com".to_string(),
+                            },
+                            offset: 19,
+                        }));
+    }
+
+    #[test]
+    fn test_parse_lenient() {
+        let raw = "addr=other@mail.com; addr=me@mail.com; _foo=one; _foo=two; broken; keydata=bXlr\x0cZXk=;";
+        let parsed = Header::parse_with_options(raw, &ParseOptions::lenient())
+            .expect("failed to parse");
+
+        assert_eq!(parsed.value.addr, "me@mail.com");
+        assert_eq!(parsed.value.get("_foo").unwrap(), "two");
+        assert_eq!(parsed.value.keydata, mykey());
+        assert_eq!(parsed.warnings,
+                   vec![ParseWarning::DuplicateAttribute {
+                            name: "addr".to_string(),
+                            ignored: "other@mail.com".to_string(),
+                        },
+                        ParseWarning::MalformedAttribute("broken".to_string()),
+                        ParseWarning::EmptyAttribute,
+                        ParseWarning::KeydataWhitespace]);
+    }
+
+    #[test]
+    fn test_limits() {
+        let limits = Limits {
+            max_header_size: 100,
+            max_attributes: 4,
+            max_attribute_length: 20,
+            max_key_size: 5,
+            max_headers: 1,
+        };
+        let options = ParseOptions::lenient().limits(limits);
+        assert!(Header::parse_with_options("addr=me@mail.com; _foo=one; keydata=bXlrZXk=",
+                                           &options)
+                        .is_ok());
+
+        let raw = format!("addr=me@mail.com; keydata=bXlrZXk=; _foo={}", "x".repeat(60));
+        assert_eq!(Header::parse_with_options(&raw, &options).err(),
+                   Some(HeaderParseError::HeaderTooLarge {
+                            size: 101,
+                            limit: 100,
+                        }));
+
+        let raw = "addr=me@mail.com; _a=1; _b=2; ; _c=3; keydata=bXlrZXk=";
+        assert_eq!(Header::parse_with_options(raw, &options).err(),
+                   Some(HeaderParseError::TooManyAttributes {
+                            limit: 4,
+                            offset: 38,
+                        }));
+
+        let raw = "addr=me@mail.com; _foo=0123456789abcdef; keydata=bXlrZXk=";
+        let err = Header::parse_with_options(raw, &options).unwrap_err();
+        assert_eq!(err,
+                   HeaderParseError::AttributeTooLong {
+                       name: "_foo".to_string(),
+                       length: 21,
+                       limit: 20,
+                       offset: 18,
+                   });
+        assert_eq!(err.attribute(), Some("_foo"));
+
+        // keydata is only bounded by its decoded size
+        let raw = "addr=me@mail.com; keydata=\r\n bXlr\r\n ZXk=";
+        assert!(Header::parse_with_options(raw, &options).is_ok());
+        let raw = "addr=me@mail.com; keydata=bXlrZXlr";
+        assert_eq!(Header::parse_with_options(raw, &options).err(),
+                   Some(HeaderParseError::KeyTooLarge {
+                            size: 6,
+                            limit: 5,
+                            offset: 26,
+                        }));
+        assert!(HeaderRef::parse_with_options(raw, &options).is_err());
+    }
+
+    #[test]
+    fn test_default_limits() {
+        let key = "a".repeat(Limits::default().max_key_size / 3 * 4 + 4);
+        let err = format!("addr=me@mail.com; keydata={}", key).parse::<Header>().unwrap_err();
+        assert_eq!(err.attribute(), Some("keydata"));
+
+        let others: String = (10..50).map(|i| format!("_{}=1; ", i)).collect();
+        let raw = format!("addr=me@mail.com; {}keydata=bXlrZXk=", others);
+        assert_eq!(raw.parse::<Header>().unwrap_err().offset(), Some(18 + 31 * 7));
+    }
+
+    #[test]
+    fn test_parse_strict() {
+        let cases = [("addr=me@mail.com; keydata=bXlrZXk=;", ParseWarning::EmptyAttribute, 35),
+                     ("addr=me@mail.com; broken; keydata=bXlrZXk=",
+                      ParseWarning::MalformedAttribute("broken".to_string()),
+                      18),
+                     ("addr=me@mail.com; keydata=bXlr\nZXk=", ParseWarning::KeydataWhitespace, 30),
+                     ("addr=me@mail.com; keydata=bXlr\r\n\r\n ZXk=",
+                      ParseWarning::KeydataWhitespace,
+                      30),
+                     ("addr=me@mail.com; prefer-encrypt=mutual; prefer-encrypt=mutual; \
+                       keydata=bXlrZXk=",
+                      ParseWarning::DuplicateAttribute {
+                          name: "prefer-encrypt".to_string(),
+                          ignored: "mutual".to_string(),
+                      },
+                      41)];
+
+        for &(raw, ref warning, offset) in cases.iter() {
+            assert_eq!(raw.parse::<Header>().err(),
+                       Some(HeaderParseError::Rejected {
+                                warning: warning.clone(),
+                                offset,
+                            }));
+
+            let parsed = Header::parse_with_options(raw, &ParseOptions::lenient())
+                .expect("failed to parse");
+            assert_eq!(parsed.warnings, vec![warning.clone()]);
+        }
+
+        let parsed = Header::parse_with_options("addr=me@mail.com; keydata=\n bXlr\r\n\tZXk=",
+                                                &ParseOptions::strict())
+                .expect("failed to parse");
+        assert!(parsed.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_error_diagnostics() {
+        use std::error::Error;
+
+        let err = "addr=me@mail.com; to=you@mail.com; keydata=bXlrZXk="
+            .parse::<Header>()
+            .unwrap_err();
+        assert_eq!(err.attribute(), Some("to"));
+        assert_eq!(err.offset(), Some(18));
+        assert_eq!(err.to_string(),
+                   "Unknown critical attribute \"to\" at offset 18, only addr, prefer-encrypt \
+                    and keydata are allowed, others must start with '_'");
+        assert!(err.source().is_none());
+
+        let err = "addr=me@mail.com; keydata=bXlr*ZXk="
+            .parse::<Header>()
+            .unwrap_err();
+        assert_eq!(err.attribute(), Some("keydata"));
+        assert_eq!(err.offset(), Some(26));
+        assert_eq!(err.source().unwrap().to_string(),
+                   "Invalid Base64: Invalid symbol 42, offset 4.");
+
+        let err = "addr=me@mail.com; addr=you@mail.com; keydata=bXlrZXk="
+            .parse::<Header>()
+            .unwrap_err();
+        assert_eq!(err.attribute(), Some("addr"));
+        assert_eq!(err.offset(), Some(18));
+    }
+
+    #[test]
+    fn test_builder() {
+        let h = Header::builder()
+            .addr("me@mail.com")
+            .prefer_encrypt(EncryptPreference::Mutual)
+            .keydata(mykey())
+            .attribute("_foo", "one")
+            .attribute("_bar", "two=2")
+            .attribute("_baz", "three")
+            .remove_attribute("_baz")
+            .build()
+            .expect("failed to build");
+
+        assert_eq!(h.to_string(),
+                   "addr=me@mail.com; prefer-encrypt=mutual; _foo=one; _bar=two=2; keydata=bXlrZXk=");
+        assert_eq!(h.to_string().parse::<Header>().unwrap(), h);
+    }
+
+    #[test]
+    fn test_builder_missing() {
+        assert_eq!(HeaderBuilder::new().keydata(mykey()).build(),
+                   Err(HeaderBuildError::MissingAttribute("addr")));
+        assert_eq!(HeaderBuilder::new().addr("me@mail.com").build(),
+                   Err(HeaderBuildError::MissingAttribute("keydata")));
+    }
+
+    #[test]
+    fn test_builder_invalid_addr() {
+        for addr in &["", "me", "@mail.com", "me@", "me@mail@com", "me @mail.com",
+                      "me@mail.com; _foo=bar", "<me@mail.com>"] {
+            assert_eq!(Header::builder().addr(*addr).keydata(mykey()).build(),
+                       Err(HeaderBuildError::InvalidAddress(addr.to_string())));
+        }
+    }
+
+    #[test]
+    fn test_builder_invalid_attributes() {
+        let builder = Header::builder().addr("me@mail.com").keydata(mykey());
+
+        for name in &["addr", "prefer-encrypt", "type", "other"] {
+            assert_eq!(builder.clone().attribute(*name, "x").build(),
+                       Err(HeaderBuildError::CriticalAttribute(name.to_string())));
+        }
+        for &(name, value) in &[("_", "x"), ("_a b", "x"), ("_a=b", "x"), ("_foo", "a;b"),
+                                ("_foo", "a\r\n b"), ("_foo", " x")] {
+            assert_eq!(builder.clone().attribute(name, value).build(),
+                       Err(HeaderBuildError::InvalidAttribute(name.to_string())));
+        }
+    }
+
+    #[test]
+    fn test_builder_long_attribute() {
+        let builder = Header::builder().addr("me@mail.com").keydata(mykey());
+
+        // the longest attribute fitting on a line
+        let value = "x".repeat(MAX_LINE_LENGTH - "_foo".len() - 3);
+        let h = builder.clone().attribute("_foo", value).build().unwrap();
+        let folded = h.to_folded();
+        assert!(folded.split("\r\n").all(|line| line.len() <= MAX_LINE_LENGTH));
+        assert_eq!(Header::from_folded(&folded).unwrap(), h);
+
+        let value = "x".repeat(2000);
+        assert_eq!(builder.attribute("_foo", value).build(),
+                   Err(HeaderBuildError::AttributeTooLong("_foo".to_string())));
+    }
+
+    #[test]
+    fn test_new_invalid_attributes() {
+        let new = |name: &str, value: &str| {
+            Header::new("me@mail.com".parse().unwrap(),
+                        KeyType::OpenPGP,
+                        EncryptPreference::None,
+                        mykey(),
+                        vec![(name.to_string(), value.to_string())])
+        };
+
+        assert!(new("_foo", "one").is_ok());
+        assert_eq!(new("prefer-encrypt", "mutual"),
+                   Err(HeaderBuildError::CriticalAttribute("prefer-encrypt".to_string())));
+        assert_eq!(new("_foo", "x; prefer-encrypt=mutual"),
+                   Err(HeaderBuildError::InvalidAttribute("_foo".to_string())));
+    }
+
+    #[test]
+    fn test_gossip_from_str() {
+        let g: GossipHeader = "addr=bob@mail.com; prefer-encrypt=mutual; _foo=one; keydata=bXlrZXk="
+            .parse()
+            .expect("failed to parse");
+
+        assert_eq!(g.addr(), "bob@mail.com");
+        assert_eq!(g.keydata(), &mykey());
+        assert_eq!(g.header().prefer_encrypt, EncryptPreference::None);
+        assert_eq!(g.header().get("_foo").unwrap(), "one");
+        assert_eq!(g.to_string(), "addr=bob@mail.com; _foo=one; keydata=bXlrZXk=");
+    }
+
+    #[test]
+    fn test_gossip_new() {
+        let g = GossipHeader::new("bob@mail.com".parse().unwrap(), keydata());
+        assert_eq!(g.to_string(),
+                   format!("addr=bob@mail.com; keydata={}", keydata()));
+    }
+
+    #[test]
+    fn test_gossip_folded() {
+        let g = GossipHeader::new("bob@mail.com".parse().unwrap(), keydata());
+        let folded = g.to_folded();
+
+        assert!(folded.starts_with("Autocrypt-Gossip: addr=bob@mail.com; keydata=\r\n "));
+        assert_eq!(GossipHeader::from_folded(&folded).expect("failed to parse"), g);
     }
 }
@@ -2,6 +2,8 @@
 use std::fs::File;
 use std::io::Read;
 
+use addr::EmailAddress;
+
 pub fn get_file<S: Into<String>>(filename: S) -> String {
     let filepath = format!("./test/fixtures/{}", filename.into());
     let mut f = File::open(filepath).expect("file not found");
@@ -12,3 +14,23 @@ pub fn get_file<S: Into<String>>(filename: S) -> String {
 
     contents
 }
+
+/// Get the raw `keydata` of the Autocrypt header in a fixture.
+pub fn get_keydata<S: Into<String>>(filename: S) -> String {
+    let contents = get_file(filename);
+    let start = contents.find("keydata=").expect("no keydata found") + "keydata=".len();
+
+    let mut keydata = String::new();
+    for (i, line) in contents[start..].lines().enumerate() {
+        if i > 0 && !line.starts_with(' ') {
+            break;
+        }
+        keydata.push_str(line.trim());
+    }
+
+    keydata
+}
+
+pub fn addr(s: &str) -> EmailAddress {
+    s.parse().expect("invalid address")
+}
@@ -0,0 +1,106 @@
+use std::{fmt, str};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+
+use errors::KeyDataParseError;
+
+/// The binary key material carried in the `keydata` attribute.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct KeyData(Vec<u8>);
+
+impl KeyData {
+    pub fn new(bytes: Vec<u8>) -> KeyData {
+        KeyData(bytes)
+    }
+
+    /// Decode Base64 encoded key material.
+    ///
+    /// Folding whitespace, as found in headers spanning multiple lines, is
+    /// stripped before decoding. Anything else that is not valid Base64 is
+    /// rejected, as is an empty key.
+    pub fn from_base64(s: &str) -> Result<KeyData, KeyDataParseError> {
+        let stripped: String = s.chars().filter(|c| !c.is_ascii_whitespace()).collect();
+        if stripped.is_empty() {
+            return Err(KeyDataParseError::Empty);
+        }
+
+        let bytes = STANDARD
+            .decode(stripped)
+            .map_err(|err| KeyDataParseError::InvalidBase64(err.to_string()))?;
+
+        Ok(KeyData(bytes))
+    }
+
+    /// Encode the key material in canonical Base64, without any whitespace.
+    pub fn to_base64(&self) -> String {
+        STANDARD.encode(&self.0)
+    }
+
+    /// The binary key material.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+}
+
+impl fmt::Display for KeyData {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", self.to_base64())
+    }
+}
+
+impl str::FromStr for KeyData {
+    type Err = KeyDataParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        KeyData::from_base64(s)
+    }
+}
+
+impl From<Vec<u8>> for KeyData {
+    fn from(bytes: Vec<u8>) -> KeyData {
+        KeyData(bytes)
+    }
+}
+
+impl AsRef<[u8]> for KeyData {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let key: KeyData = "bXlrZXk=".parse().expect("failed to parse");
+        assert_eq!(key.as_bytes(), b"mykey");
+        assert_eq!(key.to_string(), "bXlrZXk=");
+    }
+
+    #[test]
+    fn test_strips_folding_whitespace() {
+        let key: KeyData = "bXlr\r\n ZXk=\n\t".parse().expect("failed to parse");
+        assert_eq!(key.as_bytes(), b"mykey");
+        assert_eq!(key.to_base64(), "bXlrZXk=");
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(" \r\n ".parse::<KeyData>().err(),
+                   Some(KeyDataParseError::Empty));
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!("my*key".parse::<KeyData>().is_err());
+        // missing padding is rejected
+        assert!("bXlrZXk".parse::<KeyData>().is_err());
+    }
+}
@@ -2,16 +2,55 @@
 extern crate quick_error;
 extern crate email;
 extern crate time;
+extern crate base64;
+extern crate sha1;
+extern crate sha2;
+extern crate idna;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "openpgp")]
+extern crate pgp as rpgp;
+#[cfg(feature = "openpgp")]
+extern crate rand;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
 
-pub use header::Header;
-pub use types::{KeyType, EncryptPreference};
-pub use peer::PeerInfo;
+pub use errors::{Error, ErrorKind, Result};
+pub use addr::EmailAddress;
+pub use header::{Header, HeaderRef, HeaderBuilder, GossipHeader};
+pub use canonical::ContentHash;
+pub use types::{KeyType, EncryptPreference, Recommendation, SpecVersion, Dialect};
+pub use peer::{PeerInfo, PeerState};
+pub use keydata::KeyData;
+pub use pgp::{TransferablePublicKey, Fingerprint, KeyId};
+pub use validate::validate_key;
+pub use minimize::{minimize_key, MinimizedKey};
+pub use options::{ParseOptions, ParsePolicy, ParseWarning, Parsed, Limits};
+pub use crypto::{CryptoBackend, KeyAlgorithm, KeyPair, Decrypted, SignatureStatus};
+#[cfg(any(test, feature = "mock"))]
+pub use mock::MockBackend;
+#[cfg(feature = "openpgp")]
+pub use openpgp::OpenPgpBackend;
 pub mod errors;
 pub mod mime;
+pub mod pgp;
+pub mod canonical;
+pub mod crypto;
 
+mod addr;
 mod header;
 mod types;
 mod peer;
+mod keydata;
+mod validate;
+mod minimize;
+mod options;
+#[cfg(any(test, feature = "mock"))]
+mod mock;
+#[cfg(feature = "openpgp")]
+mod openpgp;
+#[cfg(feature = "serde")]
+mod serialize;
 
 #[cfg(test)]
 mod helpers;
@@ -1,37 +1,553 @@
-use email::{self, MimeMessage};
-use header::Header;
-use errors::HeaderParseError;
+use email::{self, Address, MimeMessage};
+use email::mimeheaders::MimeContentTypeHeader;
+use addr::EmailAddress;
+use header::{Header, GossipHeader};
+use peer::PeerInfo;
+use options::{ParseOptions, Parsed};
+use keydata::KeyData;
+use crypto::{CryptoBackend, SignatureStatus};
+use errors::{self, EncryptError, DecryptError, HeaderParseError, HeaderInjectError,
+             KeyValidationError};
 use time;
 
 // example: Sat, 17 Dec 2016 10:07:48 +0100
-pub static EMAIL_DATE_FMT: &'static str = "%a, %d %b %Y %T %z";
+pub static EMAIL_DATE_FMT: &str = "%a, %d %b %Y %T %z";
 
 /// Parse a string as a mime message
 pub fn parse(s: &str) -> email::results::ParsingResult<MimeMessage> {
     MimeMessage::parse(s)
 }
 
+/// Get the address from the `From` header of a parsed email.
+///
+/// If the header lists more than one mailbox, the first one is used. Returns
+/// `None` if that is not a valid address.
+pub fn get_from_addr(mail: &MimeMessage) -> Option<EmailAddress> {
+    first_mailbox(mail.headers.get("From".to_string())?)
+}
+
+fn first_mailbox(from: &email::Header) -> Option<EmailAddress> {
+    let addresses: Vec<Address> = from.get_value().ok()?;
+
+    addresses
+        .into_iter()
+        .filter_map(|address| match address {
+                        Address::Mailbox(mailbox) => Some(mailbox.address),
+                        Address::Group(_, mailboxes) => {
+                            mailboxes.into_iter().next().map(|m| m.address)
+                        }
+                    })
+        .next()
+        .and_then(|addr| addr.parse().ok())
+}
+
+/// Check if the `addr` of an Autocrypt header matches the given address.
+///
+/// Both are compared as `EmailAddress`, an invalid address matches nothing.
+pub fn addr_matches(addr: &str, other: &str) -> bool {
+    match (EmailAddress::parse(addr.trim()), EmailAddress::parse(other.trim())) {
+        (Ok(addr), Ok(other)) => addr == other,
+        _ => false,
+    }
+}
+
 /// Get the autocrypt header from a parsed email.
+///
+/// Only headers whose `addr` matches the `From` address are considered,
+/// others are ignored without being parsed. A matching header is invalid if
+/// it fails to parse, or its key does not pass `Header::validate`, which only
+/// checks the structure of the key, see `get_ac_header_verified`.
 /// Possible outcomes are
-/// - no header, `Ok(None)`
-/// - one header, valid, `Ok(Header{...})`
-/// - one header, invalid, `Err(...)`,
-/// - multiple headers, always invalid, `Err(...)`
+/// - no matching header, `Ok(None)`
+/// - one valid matching header, `Ok(Header{...})`
+/// - multiple valid matching headers, treated as no header, `Ok(None)`
+/// - only invalid matching headers, the error of the first one, `Err(...)`
+///
+/// Invalid matching headers are ignored if there is a valid one. An email
+/// with more headers than `Limits::max_headers` is rejected before any of
+/// them is looked at.
+///
+/// Headers are parsed strictly, see `get_ac_header_with_options`.
 pub fn get_ac_header(mail: &MimeMessage) -> Result<Option<Header>, HeaderParseError> {
-    let headers = mail.headers.find(&"Autocrypt".to_string());
-    if let Some(headers) = headers {
-        if headers.len() > 1 {
-            return Err(HeaderParseError::TooManyHeaders);
+    get_ac_header_with_options(mail, &ParseOptions::strict()).map(|p| p.value)
+}
+
+/// Get the autocrypt header from a parsed email, parsing with the given
+/// options.
+///
+/// Works like `get_ac_header`, returning the warnings of the selected header.
+pub fn get_ac_header_with_options(mail: &MimeMessage,
+                                  options: &ParseOptions)
+                                  -> Result<Parsed<Option<Header>>, HeaderParseError> {
+    find_ac_header(mail, options, Header::validate)
+}
+
+/// Get the autocrypt header from a parsed email, checking its key with
+/// `backend`.
+///
+/// Works like `get_ac_header_with_options`, but a matching header is only
+/// valid if its key passes `CryptoBackend::verify_key`, so a key whose self
+/// signatures do not verify is rejected.
+pub fn get_ac_header_verified<B>(backend: &B,
+                                 mail: &MimeMessage,
+                                 options: &ParseOptions)
+                                 -> Result<Parsed<Option<Header>>, HeaderParseError>
+    where B: CryptoBackend + ?Sized
+{
+    find_ac_header(mail, options, |header| backend.verify_key(&header.keydata))
+}
+
+/// Select the Autocrypt header of an email as described for
+/// `get_ac_header`, with `check` deciding whether the key of a header is
+/// valid.
+fn find_ac_header<F>(mail: &MimeMessage,
+                     options: &ParseOptions,
+                     check: F)
+                     -> Result<Parsed<Option<Header>>, HeaderParseError>
+    where F: Fn(&Header) -> Result<(), KeyValidationError>
+{
+    let none = Parsed {
+        value: None,
+        warnings: Vec::new(),
+    };
+    let headers = match mail.headers.find(&"Autocrypt".to_string()) {
+        Some(headers) => headers,
+        None => return Ok(none),
+    };
+    let limit = options.limits.max_headers;
+    if headers.len() > limit {
+        return Err(HeaderParseError::TooManyHeaders {
+                       count: headers.len(),
+                       limit,
+                   });
+    }
+    let from = match get_from_addr(mail) {
+        Some(from) => from,
+        None => return Ok(none),
+    };
+
+    let mut valid = Vec::new();
+    let mut first_err = None;
+    for header in headers {
+        let value = match header.get_value::<String>() {
+            Ok(value) => value,
+            Err(_) => continue,
+        };
+        match header_addr(&value, options.legacy) {
+            Some(addr) if addr_matches(addr, from.as_str()) => {}
+            _ => continue,
+        }
+
+        let parsed = Header::parse_with_options(&value, options).and_then(|h| {
+                                                                       check(&h.value)?;
+                                                                       Ok(h)
+                                                                   });
+        match parsed {
+            Ok(header) => valid.push(header),
+            Err(err) => {
+                first_err.get_or_insert(err);
+            }
+        }
+    }
+
+    match (valid.len(), first_err) {
+        (1, _) => Ok(valid.pop().expect("checked length").map(Some)),
+        (0, Some(err)) => Err(err),
+        _ => Ok(none),
+    }
+}
+
+/// Find the address of a raw header value, without parsing anything else.
+///
+/// If `addr` is present more than once, the last value is returned, as
+/// parsing leniently would.
+fn header_addr(value: &str, legacy: bool) -> Option<&str> {
+    value
+        .split(';')
+        .filter_map(|attribute| {
+                        let mut parts = attribute.trim().splitn(2, '=');
+                        match (parts.next(), parts.next()) {
+                            (Some("addr"), Some(addr)) => Some(addr),
+                            (Some("to"), Some(addr)) if legacy => Some(addr),
+                            _ => None,
+                        }
+                    })
+        .next_back()
+}
+
+/// Get the addresses listed in the `To` and `Cc` headers of an email.
+///
+/// Headers that fail to parse and invalid addresses are skipped.
+pub fn get_recipient_addrs(mail: &MimeMessage) -> Vec<EmailAddress> {
+    ["To", "Cc"]
+        .iter()
+        .filter_map(|name| mail.headers.find(&name.to_string()))
+        .flat_map(|headers| headers.into_iter().flat_map(mailboxes))
+        .collect()
+}
+
+fn mailboxes(header: &email::Header) -> Vec<EmailAddress> {
+    let addresses: Vec<Address> = header.get_value().unwrap_or_default();
+
+    addresses
+        .into_iter()
+        .flat_map(|address| match address {
+                      Address::Mailbox(mailbox) => vec![mailbox],
+                      Address::Group(_, mailboxes) => mailboxes,
+                  })
+        .filter_map(|mailbox| mailbox.address.parse().ok())
+        .collect()
+}
+
+/// Get the Autocrypt-Gossip headers from the decrypted inner part of an
+/// email, whose encrypted outer message is `outer`.
+///
+/// Only gossip about recipients of the outer message, see
+/// `get_recipient_addrs`, is returned, so a sender cannot inject keys for
+/// arbitrary addresses. Invalid gossip headers, including those whose key
+/// does not pass `Header::validate`, are skipped, as the spec requires them
+/// to be ignored without affecting the remaining ones. Only the structure of
+/// the keys is checked, see `get_gossip_headers_verified`.
+pub fn get_gossip_headers(outer: &MimeMessage, inner: &MimeMessage) -> Vec<GossipHeader> {
+    let recipients = get_recipient_addrs(outer);
+
+    inner
+        .headers
+        .find(&"Autocrypt-Gossip".to_string())
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|h| h.get_value::<String>().ok())
+        .filter_map(|h| h.parse::<GossipHeader>().ok())
+        .filter(|h| recipients.contains(h.addr()))
+        .filter(|h| h.header().validate().is_ok())
+        .collect()
+}
+
+/// Get the Autocrypt-Gossip headers like `get_gossip_headers`, skipping
+/// those whose key does not pass `CryptoBackend::verify_key`.
+pub fn get_gossip_headers_verified<B>(backend: &B,
+                                      outer: &MimeMessage,
+                                      inner: &MimeMessage)
+                                      -> Vec<GossipHeader>
+    where B: CryptoBackend + ?Sized
+{
+    get_gossip_headers(outer, inner)
+        .into_iter()
+        .filter(|h| backend.verify_key(&h.header().keydata).is_ok())
+        .collect()
+}
+
+/// Build the gossip headers for an outgoing encrypted email.
+///
+/// Gossip is only sent when there is more than one recipient, in which case
+/// one header is generated for every recipient with a known public key.
+/// The recipients are the peers as stored, keyed by their address.
+pub fn gossip_headers<'a, I>(recipients: I) -> Vec<GossipHeader>
+    where I: IntoIterator<Item = (&'a EmailAddress, &'a PeerInfo)>
+{
+    let recipients: Vec<_> = recipients.into_iter().collect();
+    if recipients.len() < 2 {
+        return Vec::new();
+    }
+
+    recipients
+        .into_iter()
+        .filter_map(|(addr, peer)| {
+                        peer.public_key
+                            .as_ref()
+                            .map(|key| GossipHeader::new(addr.clone(), key.clone()))
+                    })
+        .collect()
+}
+
+/// Add the given gossip headers to the inner part of an outgoing email,
+/// before it gets encrypted.
+///
+/// The headers are folded, see `GossipHeader::to_folded`.
+pub fn add_gossip_headers(inner: &mut MimeMessage, gossip: &[GossipHeader]) {
+    for header in gossip {
+        let value = header.header().folded_value("Autocrypt-Gossip");
+        inner
+            .headers
+            .insert(email::Header::new("Autocrypt-Gossip".to_string(), value));
+    }
+}
+
+/// Encrypt an outgoing email into a PGP/MIME message, as described in
+/// RFC 3156.
+///
+/// The whole of `mail` is encrypted, including any gossip headers. The
+/// outer message keeps the other headers of `mail`, except for the
+/// `Content-*` headers describing its body. The message is encrypted to the
+/// keys of all `recipients` and to `own_key`, failing if one of the
+/// recipients has no known key. It is signed with `signer`, our secret key,
+/// if given.
+pub fn encrypt_message<'a, B, I>(backend: &B,
+                                 mail: &MimeMessage,
+                                 own_key: &KeyData,
+                                 signer: Option<&KeyData>,
+                                 recipients: I)
+                                 -> errors::Result<MimeMessage>
+    where B: CryptoBackend + ?Sized,
+          I: IntoIterator<Item = (&'a EmailAddress, &'a PeerInfo)>
+{
+    let mut keys = Vec::new();
+    for (addr, peer) in recipients {
+        let key = peer.public_key
+            .as_ref()
+            .ok_or_else(|| EncryptError::MissingKey(addr.to_string()))?;
+        keys.push(key.clone());
+    }
+    keys.push(own_key.clone());
+
+    let armored = backend.encrypt(mail.as_string().as_bytes(), &keys, signer)?;
+
+    let mut version = MimeMessage::new("Version: 1".to_string());
+    version.headers.insert(email::Header::new("Content-Type".to_string(),
+                                              "application/pgp-encrypted".to_string()));
+    version.headers.insert(email::Header::new("Content-Description".to_string(),
+                                              "PGP/MIME version identification".to_string()));
+
+    let mut payload = MimeMessage::new(armored.lines().collect::<Vec<_>>().join("\r\n"));
+    payload.headers.insert(email::Header::new("Content-Type".to_string(),
+                                              "application/octet-stream; name=\"encrypted.asc\""
+                                                  .to_string()));
+    payload.headers.insert(email::Header::new("Content-Description".to_string(),
+                                              "OpenPGP encrypted message".to_string()));
+    payload.headers.insert(email::Header::new("Content-Disposition".to_string(),
+                                              "inline; filename=\"encrypted.asc\"".to_string()));
+
+    let mut outer = MimeMessage::new_blank_message();
+    outer.body = "This is an OpenPGP/MIME encrypted message (RFC 4880 and 3156)".to_string();
+    outer.children = vec![version, payload];
+    for h in mail.headers.iter() {
+        let name = h.name.to_ascii_lowercase();
+        if !name.starts_with("content-") && name != "autocrypt-gossip" {
+            outer.headers.insert(h.clone());
+        }
+    }
+    // folded by hand, the line would be too long otherwise
+    let content_type = format!("multipart/encrypted;\r\n \
+                                protocol=\"application/pgp-encrypted\";\r\n \
+                                boundary=\"{}\"",
+                               outer.boundary);
+    outer
+        .headers
+        .insert(email::Header::new("Content-Type".to_string(), content_type));
+
+    Ok(outer)
+}
+
+/// An email decrypted by `decrypt_message`.
+#[derive(Debug, Clone)]
+pub struct DecryptedMessage {
+    /// The inner message, whose headers include any gossip.
+    pub message: MimeMessage,
+    pub signature: SignatureStatus,
+}
+
+impl DecryptedMessage {
+    /// The gossip headers of the decrypted message, whose keys pass
+    /// `CryptoBackend::verify_key`. `outer` is the encrypted email the
+    /// message was decrypted from, see `get_gossip_headers_verified`.
+    ///
+    /// Each header can be passed to `PeerInfo::update_gossip` of the peer
+    /// it is about.
+    pub fn gossip_headers<B>(&self, backend: &B, outer: &MimeMessage) -> Vec<GossipHeader>
+        where B: CryptoBackend + ?Sized
+    {
+        get_gossip_headers_verified(backend, outer, &self.message)
+    }
+}
+
+/// Decrypt an incoming PGP/MIME message, as described in RFC 3156.
+///
+/// Returns `None` if `mail` is not `multipart/encrypted`. Otherwise the
+/// payload is decrypted with `secret_key`, and its signature checked against
+/// the keys of `senders`. The gossip of the returned inner message is
+/// available through `DecryptedMessage::gossip_headers`.
+pub fn decrypt_message<B>(backend: &B,
+                          mail: &MimeMessage,
+                          secret_key: &KeyData,
+                          senders: &[KeyData])
+                          -> errors::Result<Option<DecryptedMessage>>
+    where B: CryptoBackend + ?Sized
+{
+    let content_type = match get_content_type(mail) {
+        Some(content_type) => content_type,
+        None => return Ok(None),
+    };
+    if !is_content_type(&content_type, "multipart", "encrypted") {
+        return Ok(None);
+    }
+    let protocol = content_type
+        .params
+        .iter()
+        .find(|&(name, _)| name.eq_ignore_ascii_case("protocol"))
+        .map(|(_, value)| value.as_str());
+    if !protocol.is_some_and(|p| p.eq_ignore_ascii_case("application/pgp-encrypted")) {
+        return Err(DecryptError::InvalidStructure("unsupported protocol").into());
+    }
+
+    if mail.children.len() != 2 {
+        return Err(DecryptError::InvalidStructure("expected two parts").into());
+    }
+    let (version, payload) = (&mail.children[0], &mail.children[1]);
+    let version_type = get_content_type(version);
+    if !version_type.is_some_and(|c| is_content_type(&c, "application", "pgp-encrypted")) ||
+       !version.body.lines().any(|l| l.trim() == "Version: 1") {
+        return Err(DecryptError::InvalidStructure("missing version identification").into());
+    }
+    let payload_type = get_content_type(payload);
+    if !payload_type.is_some_and(|c| is_content_type(&c, "application", "octet-stream")) {
+        return Err(DecryptError::InvalidStructure("missing encrypted payload").into());
+    }
+    let ciphertext = payload
+        .decoded_body_bytes()
+        .ok_or(DecryptError::InvalidStructure("invalid transfer encoding"))?;
+
+    let decrypted = backend.decrypt(&ciphertext, secret_key, senders)?;
+    let inner = String::from_utf8(decrypted.data).map_err(|_| DecryptError::InvalidUtf8)?;
+    // the email parser only understands CRLF line endings
+    let inner = inner.replace("\r\n", "\n").replace('\n', "\r\n");
+
+    Ok(Some(DecryptedMessage {
+                message: MimeMessage::parse(&inner)?,
+                signature: decrypted.signature,
+            }))
+}
+
+fn get_content_type(mail: &MimeMessage) -> Option<MimeContentTypeHeader> {
+    mail.headers.get_value("Content-Type".to_string()).ok()
+}
+
+fn is_content_type(content_type: &MimeContentTypeHeader, major: &str, minor: &str) -> bool {
+    content_type.content_type.0.eq_ignore_ascii_case(major) &&
+    content_type.content_type.1.eq_ignore_ascii_case(minor)
+}
+
+/// Set the Autocrypt header of an outgoing email.
+///
+/// Any existing `Autocrypt` header is replaced, the new one takes the place
+/// of the first one removed, or goes last. The header is folded, see
+/// `Header::to_folded`. Fails without changing `mail` if the address in
+/// `From` does not match `header.addr`.
+pub fn set_ac_header(mail: &mut MimeMessage, header: &Header) -> Result<(), HeaderInjectError> {
+    check_from(mail.headers.get("From".to_string()), header)?;
+
+    let value = header.folded_value("Autocrypt");
+    let mut headers = email::HeaderMap::new();
+    let mut new_header = Some(email::Header::new("Autocrypt".to_string(), value));
+    for h in mail.headers.iter() {
+        if !h.name.eq_ignore_ascii_case("Autocrypt") {
+            headers.insert(h.clone());
+        } else if let Some(new_header) = new_header.take() {
+            headers.insert(new_header);
         }
+    }
+    if let Some(new_header) = new_header {
+        headers.insert(new_header);
+    }
+    mail.headers = headers;
+
+    Ok(())
+}
+
+/// Set the Autocrypt header of an outgoing email in raw RFC 5322 form.
+///
+/// Works like `set_ac_header`, leaving everything but the `Autocrypt`
+/// headers untouched. The line endings of the message are kept.
+pub fn set_ac_header_raw(raw: &[u8], header: &Header) -> Result<Vec<u8>, HeaderInjectError> {
+    let (fields, body) = split_header_fields(raw);
+    let crlf = raw
+        .iter()
+        .position(|b| *b == b'\n')
+        .map(|i| i > 0 && raw[i - 1] == b'\r')
+        .unwrap_or(true);
+
+    let from = fields
+        .iter()
+        .find(|field| is_field(field, "From"))
+        .map(|field| {
+                 let field = String::from_utf8_lossy(field);
+                 let value = field[field.find(':').unwrap() + 1..].replace(['\r', '\n'], "");
+                 email::Header::new("From".to_string(), value.trim().to_string())
+             });
+    check_from(from.as_ref(), header)?;
+
+    let mut folded = header.to_folded();
+    folded.push_str("\r\n");
+    if !crlf {
+        folded = folded.replace("\r\n", "\n");
+    }
+
+    let mut out = Vec::with_capacity(raw.len() + folded.len());
+    let mut new_header = Some(folded);
+    for field in fields {
+        if !is_field(field, "Autocrypt") {
+            out.extend_from_slice(field);
+        } else if let Some(new_header) = new_header.take() {
+            out.extend_from_slice(new_header.as_bytes());
+        }
+    }
+    if let Some(new_header) = new_header {
+        if !out.is_empty() && !out.ends_with(b"\n") {
+            out.extend_from_slice(if crlf { b"\r\n" } else { b"\n" });
+        }
+        out.extend_from_slice(new_header.as_bytes());
+    }
+    out.extend_from_slice(body);
+
+    Ok(out)
+}
+
+fn check_from(from: Option<&email::Header>, header: &Header) -> Result<(), HeaderInjectError> {
+    let from = from.ok_or(HeaderInjectError::MissingFrom)?;
+    let addr = first_mailbox(from).ok_or(HeaderInjectError::MissingFrom)?;
+    if addr != header.addr {
+        return Err(HeaderInjectError::FromMismatch {
+                       from: addr.to_string(),
+                       addr: header.addr.to_string(),
+                   });
+    }
 
-        let header: String = headers[0]
-            .get_value()
-            .map_err(|_| HeaderParseError::InvalidHeader)?;
+    Ok(())
+}
 
-        return header.parse().map(|h| Some(h));
+/// Split a raw message into its header fields, including their
+/// continuation lines and line endings, and the rest of the message,
+/// starting with the empty line separating the body.
+fn split_header_fields(raw: &[u8]) -> (Vec<&[u8]>, &[u8]) {
+    let mut fields: Vec<&[u8]> = Vec::new();
+    let mut start = 0;
+    let mut field_start = 0;
+    while start < raw.len() {
+        let end = raw[start..]
+            .iter()
+            .position(|b| *b == b'\n')
+            .map(|i| start + i + 1)
+            .unwrap_or(raw.len());
+        let line = &raw[start..end];
+        if line == b"\r\n" || line == b"\n" {
+            break;
+        }
+        let continuation = line[0] == b' ' || line[0] == b'\t';
+        if !continuation && start > field_start {
+            fields.push(&raw[field_start..start]);
+            field_start = start;
+        }
+        start = end;
+    }
+    if start > field_start {
+        fields.push(&raw[field_start..start]);
     }
 
-    Ok(None)
+    (fields, &raw[start..])
+}
+
+fn is_field(field: &[u8], name: &str) -> bool {
+    field.len() > name.len() && field[..name.len()].eq_ignore_ascii_case(name.as_bytes()) &&
+    matches!(field[name.len()..].iter().find(|b| **b != b' ' && **b != b'\t'), Some(&b':'))
 }
 
 /// Get the effective date of this email.
@@ -53,7 +569,15 @@ pub fn get_effective_date(mail: &MimeMessage) -> time::Tm {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::BTreeMap;
     use helpers::*;
+    use keydata::KeyData;
+    use options::{Limits, ParseWarning};
+    use types::{KeyType, EncryptPreference};
+    use crypto::{KeyAlgorithm, KeyPair};
+    use mock::MockBackend;
+    use header::HeaderBuilder;
+    use peer::PeerState;
 
     #[test]
     fn test_parse_simple() {
@@ -70,6 +594,7 @@ mod tests {
             .expect("failed to get ac header")
             .unwrap();
         assert_eq!(header.addr, "alice@testsuite.autocrypt.org");
+        assert_eq!(header.parse_key().expect("failed to parse key").subkeys.len(), 1);
     }
 
     #[test]
@@ -81,6 +606,545 @@ mod tests {
         assert!(header.is_none());
     }
 
+    fn mail_with_headers(from: &str, headers: &[String]) -> MimeMessage {
+        let mut raw = format!("From: {}\r\n", from);
+        for header in headers {
+            raw.push_str(&format!("Autocrypt: {}\r\n", header));
+        }
+        raw.push_str("Content-Type: text/plain\r\n\r\nhello\r\n");
+
+        parse(&raw).expect("failed to parse")
+    }
+
+    #[test]
+    fn test_get_from_addr() {
+        let file = get_file("rsa2048-simple.eml");
+        let mail = parse(&file).expect("failed to parse");
+
+        assert_eq!(get_from_addr(&mail).unwrap(),
+                   "alice@testsuite.autocrypt.org");
+    }
+
+    #[test]
+    fn test_addr_matches() {
+        assert!(addr_matches("Alice@Mail.com", "alice@mail.com"));
+        assert!(addr_matches("alice@bücher.example", "ALICE@xn--bcher-kva.example"));
+        assert!(!addr_matches("alice@mail.com", "bob@mail.com"));
+        assert!(!addr_matches("alice", "alice"));
+    }
+
+    #[test]
+    fn test_get_ac_header_normalized_addr() {
+        let key = get_keydata("ed25519-simple.eml");
+        let mail = mail_with_headers("Alice <Alice@MAIL.com>",
+                                     &[format!("addr=alice@mail.com; keydata={}", key)]);
+
+        let header = get_ac_header(&mail)
+            .expect("failed to get ac header")
+            .unwrap();
+        assert_eq!(header.addr, get_from_addr(&mail).unwrap());
+        assert_eq!(header.addr.as_str(), "alice@mail.com");
+    }
+
+    #[test]
+    fn test_get_ac_header_invalid_addr() {
+        let mail = mail_with_headers("Alice <alice@mail.com>",
+                                     &["addr=alice@@mail.com; keydata=bXlrZXk=".to_string()]);
+
+        // an invalid address never matches `From`
+        let header = get_ac_header(&mail).expect("failed to get ac header");
+        assert!(header.is_none());
+    }
+
+    #[test]
+    fn test_get_ac_header_ignores_other_addr() {
+        let key = get_keydata("ed25519-simple.eml");
+        let mail = mail_with_headers("Alice <alice@mail.com>",
+                                     &[format!("addr=bob@mail.com; keydata={}",
+                                               get_keydata("rsa2048-simple.eml")),
+                                       format!("addr=alice@mail.com; keydata={}", key)]);
+
+        let header = get_ac_header(&mail)
+            .expect("failed to get ac header")
+            .unwrap();
+        assert_eq!(header.addr, "alice@mail.com");
+        assert_eq!(header.keydata.to_base64(), key);
+    }
+
+    #[test]
+    fn test_get_ac_header_single_other_addr() {
+        let mail = mail_with_headers("Alice <alice@mail.com>",
+                                     &[format!("addr=bob@mail.com; keydata={}",
+                                               get_keydata("rsa2048-simple.eml"))]);
+
+        let header = get_ac_header(&mail).expect("failed to get ac header");
+        assert!(header.is_none());
+    }
+
+    #[test]
+    fn test_get_ac_header_multiple_matching() {
+        let mail = mail_with_headers("Alice <alice@mail.com>",
+                                     &[format!("addr=alice@mail.com; keydata={}",
+                                               get_keydata("ed25519-simple.eml")),
+                                       format!("addr=alice@mail.com; keydata={}",
+                                               get_keydata("rsa2048-simple.eml"))]);
+
+        let header = get_ac_header(&mail).expect("failed to get ac header");
+        assert!(header.is_none());
+    }
+
+    #[test]
+    fn test_get_ac_header_ignores_invalid() {
+        let key = get_keydata("rsa2048-simple.eml");
+        let mail = mail_with_headers("Alice <alice@mail.com>",
+                                     &[format!("addr=alice@mail.com; other=me; keydata={}",
+                                               get_keydata("ed25519-simple.eml")),
+                                       "addr=alice@mail.com; keydata=bXlrZXk=".to_string(),
+                                       format!("addr=alice@mail.com; keydata={}", key)]);
+
+        let header = get_ac_header(&mail)
+            .expect("failed to get ac header")
+            .unwrap();
+        assert_eq!(header.keydata.to_base64(), key);
+    }
+
+    #[test]
+    fn test_get_ac_header_single_invalid() {
+        let mail = mail_with_headers("Alice <alice@mail.com>",
+                                     &["addr=alice@mail.com; other=me; keydata=bXlrZXk=".to_string()]);
+
+        assert_eq!(get_ac_header(&mail).err(),
+                   Some(HeaderParseError::UnknownCriticalAttribute {
+                            name: "other".to_string(),
+                            offset: 21,
+                        }));
+    }
+
+    #[test]
+    fn test_get_ac_header_invalid_among_others() {
+        let mail = mail_with_headers("Alice <alice@mail.com>",
+                                     &[format!("addr=bob@mail.com; keydata={}",
+                                               get_keydata("rsa2048-simple.eml")),
+                                       "addr=alice@mail.com; other=me; keydata=bXlrZXk=".to_string()]);
+
+        assert_eq!(get_ac_header(&mail).err(),
+                   Some(HeaderParseError::UnknownCriticalAttribute {
+                            name: "other".to_string(),
+                            offset: 21,
+                        }));
+    }
+
+    #[test]
+    fn test_get_ac_header_too_many() {
+        let header = format!("addr=alice@mail.com; keydata={}", get_keydata("ed25519-simple.eml"));
+        let mail = mail_with_headers("Alice <alice@mail.com>", &[header.clone(), header]);
+        let limits = Limits { max_headers: 1, ..Limits::default() };
+
+        assert_eq!(get_ac_header_with_options(&mail, &ParseOptions::strict().limits(limits)).err(),
+                   Some(HeaderParseError::TooManyHeaders { count: 2, limit: 1 }));
+    }
+
+    #[test]
+    fn test_header_addr() {
+        assert_eq!(header_addr("addr=a@b.c; keydata=x", false), Some("a@b.c"));
+        assert_eq!(header_addr("addr=a@b.c; addr=d@e.f; keydata=x", false), Some("d@e.f"));
+        assert_eq!(header_addr("to=a@b.c; keydata=x", false), None);
+        assert_eq!(header_addr("to=a@b.c; keydata=x", true), Some("a@b.c"));
+    }
+
+    #[test]
+    fn test_get_ac_header_single_invalid_key() {
+        let mail = mail_with_headers("Alice <alice@mail.com>",
+                                     &["addr=alice@mail.com; keydata=bXlrZXk=".to_string()]);
+
+        match get_ac_header(&mail) {
+            Err(HeaderParseError::InvalidKey(_)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_get_ac_header_lenient() {
+        let key = get_keydata("ed25519-simple.eml");
+        let mail = mail_with_headers("Alice <alice@mail.com>",
+                                     &[format!("addr=alice@mail.com; _foo=1; _foo=2; keydata={};",
+                                               key)]);
+
+        assert_eq!(get_ac_header(&mail).err(),
+                   Some(HeaderParseError::Rejected {
+                            warning: ParseWarning::EmptyAttribute,
+                            offset: 46 + key.len(),
+                        }));
+
+        let parsed = get_ac_header_with_options(&mail, &ParseOptions::lenient())
+            .expect("failed to get ac header");
+        let header = parsed.value.unwrap();
+        assert_eq!(header.keydata.to_base64(), key);
+        assert_eq!(header.get("_foo").unwrap(), "2");
+        assert_eq!(parsed.warnings, vec![ParseWarning::EmptyAttribute]);
+    }
+
+    #[test]
+    fn test_gossip_roundtrip() {
+        let key: KeyData = get_keydata("ed25519-simple.eml").parse().unwrap();
+        let bob = PeerInfo::new(time::now_utc(), None, Some(key.clone()), None);
+        let carol = PeerInfo::new(time::now_utc(), None, Some(key.clone()), None);
+        let dave = PeerInfo::new(time::now_utc(), None, None, None);
+
+        let mut peers = BTreeMap::new();
+        peers.insert(addr("bob@mail.com"), bob);
+        peers.insert(addr("carol@mail.com"), carol);
+        peers.insert(addr("dave@mail.com"), dave);
+
+        let gossip = gossip_headers(&peers);
+        assert_eq!(gossip.len(), 2);
+
+        let mut inner = MimeMessage::new("hello".to_string());
+        add_gossip_headers(&mut inner, &gossip);
+
+        let raw = inner.as_string();
+        let head = &raw[..raw.find("\r\n\r\n").expect("missing body")];
+        assert!(head.split("\r\n").all(|line| line.len() <= 78));
+
+        let outer = parse("To: bob@mail.com\r\nCc: Carol <carol@mail.com>, dave@mail.com\r\n\r\n")
+            .expect("failed to parse");
+        let parsed = parse(&raw).expect("failed to parse");
+        let found = get_gossip_headers(&outer, &parsed);
+        assert_eq!(found, gossip);
+        assert_eq!(found[0].addr(), "bob@mail.com");
+        assert_eq!(found[1].addr(), "carol@mail.com");
+    }
+
+    #[test]
+    fn test_gossip_headers_single_recipient() {
+        let bob = PeerInfo::new(time::now_utc(), None, Some(KeyData::new(b"mykey".to_vec())), None);
+        assert!(gossip_headers(vec![(&addr("bob@mail.com"), &bob)]).is_empty());
+    }
+
+    #[test]
+    fn test_get_gossip_headers_skips_invalid() {
+        let mut inner = MimeMessage::new("hello".to_string());
+        let key = get_keydata("rsa2048-simple.eml");
+        for value in &[format!("addr=bob@mail.com; other=1; keydata={}", key),
+                       "addr=dave@mail.com; keydata=bXlrZXk=".to_string(),
+                       format!("addr=carol@mail.com; keydata={}", key)] {
+            inner
+                .headers
+                .insert(email::Header::new("Autocrypt-Gossip".to_string(), value.clone()));
+        }
+
+        let outer = parse("To: bob@mail.com, carol@mail.com, dave@mail.com\r\n\r\n")
+            .expect("failed to parse");
+        let found = get_gossip_headers(&outer, &inner);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].addr(), "carol@mail.com");
+    }
+
+    #[test]
+    fn test_get_gossip_headers_skips_non_recipients() {
+        let key: KeyData = get_keydata("rsa2048-simple.eml").parse().unwrap();
+        let mut inner = MimeMessage::new("hello".to_string());
+        add_gossip_headers(&mut inner,
+                           &[GossipHeader::new(addr("bob@mail.com"), key.clone()),
+                             GossipHeader::new(addr("eve@mail.com"), key.clone()),
+                             GossipHeader::new(addr("carol@mail.com"), key)]);
+
+        let outer = parse("To: Bob <BOB@mail.com>\r\nCc: Friends: carol@mail.com;\r\n\r\n")
+            .expect("failed to parse");
+        let found: Vec<_> = get_gossip_headers(&outer, &inner)
+            .iter()
+            .map(|h| h.addr().to_string())
+            .collect();
+        assert_eq!(found, vec!["bob@mail.com", "carol@mail.com"]);
+
+        let outer = parse("From: bob@mail.com\r\n\r\n").expect("failed to parse");
+        assert!(get_gossip_headers(&outer, &inner).is_empty());
+    }
+
+    #[test]
+    fn test_get_recipient_addrs() {
+        let mail = parse("To: Bob <bob@mail.com>\r\nCc: carol@mail.com\r\n\r\n")
+            .expect("failed to parse");
+        assert_eq!(get_recipient_addrs(&mail),
+                   vec![addr("bob@mail.com"), addr("carol@mail.com")]);
+    }
+
+    fn key_pair(from: &str) -> KeyPair {
+        MockBackend
+            .generate_key(&addr(from), KeyAlgorithm::Ed25519)
+            .expect("failed to generate key")
+    }
+
+    #[test]
+    fn test_encrypt_message() {
+        let alice = key_pair("alice@mail.com");
+        let bob = key_pair("bob@mail.com");
+        let gossip_key: KeyData = get_keydata("rsa2048-simple.eml").parse().unwrap();
+        let bob_peer = PeerInfo::new(time::now_utc(), None, Some(bob.public.clone()), None);
+
+        let mut mail = mail_with_headers("Alice <alice@mail.com>", &[]);
+        mail.headers.insert(email::Header::new("Subject".to_string(), "secret".to_string()));
+        add_gossip_headers(&mut mail, &[GossipHeader::new(addr("bob@mail.com"), gossip_key)]);
+
+        let bob_addr = addr("bob@mail.com");
+        let recipients = vec![(&bob_addr, &bob_peer)];
+        let encrypted = encrypt_message(&MockBackend, &mail, &alice.public, None, recipients)
+            .expect("failed to encrypt");
+        let raw = encrypted.as_string();
+        assert!(raw.split("\r\n").all(|line| line.len() <= 78));
+
+        let parsed = parse(&raw).expect("failed to parse");
+        let names: Vec<_> = parsed.headers.iter().map(|h| h.name.clone()).collect();
+        assert_eq!(names, vec!["From", "Subject", "Content-Type"]);
+        let content_type: String = parsed.headers.get_value("Content-Type".to_string()).unwrap();
+        assert!(content_type.starts_with("multipart/encrypted;"));
+        assert!(content_type.contains("protocol=\"application/pgp-encrypted\""));
+
+        assert_eq!(parsed.children.len(), 2);
+        let version = &parsed.children[0];
+        assert_eq!(version.headers.get_value::<String>("Content-Type".to_string()).unwrap(),
+                   "application/pgp-encrypted");
+        assert_eq!(version.body.trim(), "Version: 1");
+
+        let payload = &parsed.children[1];
+        assert!(payload
+                    .headers
+                    .get_value::<String>("Content-Type".to_string())
+                    .unwrap()
+                    .starts_with("application/octet-stream"));
+        // encrypted to both bob and ourselves
+        for secret in &[&bob.secret, &alice.secret] {
+            let inner = MockBackend
+                .decrypt(payload.body.as_bytes(), secret, &[])
+                .expect("failed to decrypt");
+            assert_eq!(inner.data, mail.as_string().into_bytes());
+            assert_eq!(inner.signature, SignatureStatus::Unsigned);
+        }
+    }
+
+    #[test]
+    fn test_encrypt_message_missing_key() {
+        let alice = key_pair("alice@mail.com");
+        let bob = PeerInfo::new(time::now_utc(), None, None, None);
+        let mail = mail_with_headers("Alice <alice@mail.com>", &[]);
+
+        let bob_addr = addr("bob@mail.com");
+        let recipients = vec![(&bob_addr, &bob)];
+        let err = encrypt_message(&MockBackend, &mail, &alice.public, None, recipients)
+            .unwrap_err();
+        assert_eq!(err.kind(), errors::ErrorKind::Key);
+        assert_eq!(err.to_string(),
+                   "Failed to encrypt email: No public key known for bob@mail.com");
+    }
+
+    fn encrypted_mail() -> (MimeMessage, MimeMessage) {
+        let alice = key_pair("alice@mail.com");
+        let bob = key_pair("bob@mail.com");
+        let gossip_key: KeyData = get_keydata("rsa2048-simple.eml").parse().unwrap();
+        let bob_peer = PeerInfo::new(time::now_utc(), None, Some(bob.public), None);
+
+        let mut mail = mail_with_headers("Alice <alice@mail.com>", &[]);
+        mail.headers
+            .insert(email::Header::new("To".to_string(), "bob@mail.com".to_string()));
+        add_gossip_headers(&mut mail, &[GossipHeader::new(addr("bob@mail.com"), gossip_key)]);
+        let bob_addr = addr("bob@mail.com");
+        let encrypted = encrypt_message(&MockBackend,
+                                        &mail,
+                                        &alice.public,
+                                        Some(&alice.secret),
+                                        vec![(&bob_addr, &bob_peer)])
+            .expect("failed to encrypt");
+
+        (mail, parse(&encrypted.as_string()).expect("failed to parse"))
+    }
+
+    #[test]
+    fn test_decrypt_message() {
+        let alice = key_pair("alice@mail.com");
+        let bob = key_pair("bob@mail.com");
+        let (mail, encrypted) = encrypted_mail();
+
+        let senders = vec![alice.public.clone()];
+        let decrypted = decrypt_message(&MockBackend, &encrypted, &bob.secret, &senders)
+            .expect("failed to decrypt")
+            .unwrap();
+        let names: Vec<_> = decrypted.message.headers.iter().map(|h| h.name.clone()).collect();
+        assert_eq!(names, vec!["From", "Content-Type", "To", "Autocrypt-Gossip"]);
+        assert_eq!(get_from_addr(&decrypted.message), get_from_addr(&mail));
+        let gossip = get_gossip_headers(&encrypted, &decrypted.message);
+        assert_eq!(gossip.len(), 1);
+        assert_eq!(gossip, get_gossip_headers(&mail, &mail));
+        assert_eq!(decrypted.message.body.trim(), "hello");
+        assert_eq!(decrypted.signature,
+                   SignatureStatus::Valid(::pgp::fingerprint(&alice.public).unwrap()));
+
+        let decrypted = decrypt_message(&MockBackend, &encrypted, &alice.secret, &[])
+            .expect("failed to decrypt")
+            .unwrap();
+        let key_id = ::pgp::fingerprint(&alice.public).unwrap().key_id();
+        assert_eq!(decrypted.signature, SignatureStatus::UnknownKey(key_id));
+    }
+
+    #[test]
+    fn test_decrypt_message_update_peers() {
+        let alice = key_pair("alice@mail.com");
+        let bob = key_pair("bob@mail.com");
+        let carol = key_pair("carol@mail.com");
+        let (bob_addr, carol_addr) = (addr("bob@mail.com"), addr("carol@mail.com"));
+        let bob_peer = PeerInfo::new(time::now_utc(), None, Some(bob.public.clone()), None);
+        let carol_peer = PeerInfo::new(time::now_utc(), None, Some(carol.public.clone()), None);
+        let recipients = vec![(&bob_addr, &bob_peer), (&carol_addr, &carol_peer)];
+
+        // alice writes to bob and carol
+        let mut mail = mail_with_headers("alice@mail.com", &[]);
+        mail.headers.insert(email::Header::new("To".to_string(),
+                                               "bob@mail.com, carol@mail.com".to_string()));
+        let header = HeaderBuilder::new()
+            .addr("alice@mail.com")
+            .prefer_encrypt(EncryptPreference::Mutual)
+            .keydata(alice.public.clone())
+            .build()
+            .unwrap();
+        set_ac_header(&mut mail, &header).unwrap();
+        add_gossip_headers(&mut mail, &gossip_headers(recipients.clone()));
+        let encrypted = encrypt_message(&MockBackend,
+                                        &mail,
+                                        &alice.public,
+                                        Some(&alice.secret),
+                                        recipients)
+                .unwrap();
+        let encrypted = parse(&encrypted.as_string()).unwrap();
+
+        // bob decrypts it, learning about alice and carol
+        let mut alice_peer = PeerInfo::new(time::now_utc(), None, None, None);
+        alice_peer
+            .update_verified(&MockBackend, &encrypted, &ParseOptions::strict())
+            .unwrap();
+        assert_eq!(alice_peer.public_key, Some(alice.public.clone()));
+        assert_eq!(alice_peer.state, PeerState::Mutual);
+
+        let senders = vec![alice.public.clone()];
+        let decrypted = decrypt_message(&MockBackend, &encrypted, &bob.secret, &senders)
+            .unwrap()
+            .unwrap();
+        let gossip = decrypted.gossip_headers(&MockBackend, &encrypted);
+        let addrs: Vec<_> = gossip.iter().map(|h| h.addr().clone()).collect();
+        assert_eq!(addrs, vec![bob_addr, carol_addr.clone()]);
+
+        let mut carol_peer = PeerInfo::new(time::now_utc(), None, None, None);
+        for h in gossip.iter().filter(|h| *h.addr() == carol_addr) {
+            carol_peer.update_gossip(h);
+        }
+        assert_eq!(carol_peer.public_key, Some(carol.public));
+        assert_eq!(carol_peer.state, PeerState::Gossip);
+
+        // gossip never replaces a key sent by the peer itself
+        alice_peer.update_gossip(&gossip[0]);
+        assert_eq!(alice_peer.public_key, Some(alice.public));
+        assert_eq!(alice_peer.state, PeerState::Mutual);
+    }
+
+    #[test]
+    fn test_decrypt_message_not_encrypted() {
+        let alice = key_pair("alice@mail.com");
+        let mail = parse(&get_file("rsa2048-simple.eml")).expect("failed to parse");
+        assert!(decrypt_message(&MockBackend, &mail, &alice.secret, &[])
+                    .unwrap()
+                    .is_none());
+    }
+
+    #[test]
+    fn test_decrypt_message_errors() {
+        let (_, mut encrypted) = encrypted_mail();
+        let carol = key_pair("carol@mail.com");
+
+        let err = decrypt_message(&MockBackend, &encrypted, &carol.secret, &[]).unwrap_err();
+        assert_eq!(err.kind(), errors::ErrorKind::Crypto);
+
+        let alice = key_pair("alice@mail.com");
+        encrypted.children.remove(0);
+        let err = decrypt_message(&MockBackend, &encrypted, &alice.secret, &[]).unwrap_err();
+        assert_eq!(err.kind(), errors::ErrorKind::Mime);
+        assert_eq!(err.to_string(),
+                   "Failed to decrypt email: Invalid PGP/MIME structure: expected two parts");
+    }
+
+    fn account_header(from: &str) -> Header {
+        let key: KeyData = get_keydata("ed25519-simple.eml").parse().unwrap();
+        Header::new(addr(from), KeyType::OpenPGP, EncryptPreference::Mutual, key, vec![]).unwrap()
+    }
+
+    #[test]
+    fn test_set_ac_header() {
+        let header = account_header("alice@mail.com");
+        let mut mail = mail_with_headers("Alice <Alice@Mail.com>",
+                                         &["addr=alice@mail.com; keydata=b2xk".to_string(),
+                                           "addr=alice@mail.com; keydata=b3RoZXI=".to_string()]);
+        set_ac_header(&mut mail, &header).expect("failed to set header");
+
+        let names: Vec<_> = mail.headers.iter().map(|h| h.name.clone()).collect();
+        assert_eq!(names, vec!["From", "Autocrypt", "Content-Type"]);
+
+        let raw = mail.as_string();
+        let head = &raw[..raw.find("\r\n\r\n").expect("missing body")];
+        assert!(head.split("\r\n").all(|line| line.len() <= 78));
+
+        let parsed = parse(&raw).expect("failed to parse");
+        assert_eq!(get_ac_header(&parsed).unwrap(), Some(header.clone()));
+
+        let mut mail = mail_with_headers("alice@mail.com", &[]);
+        set_ac_header(&mut mail, &header).expect("failed to set header");
+        assert_eq!(get_ac_header(&mail).unwrap(), Some(header));
+    }
+
+    #[test]
+    fn test_set_ac_header_from_mismatch() {
+        let header = account_header("alice@mail.com");
+        let mut mail = mail_with_headers("Bob <bob@mail.com>",
+                                         &["addr=bob@mail.com; keydata=bXlrZXk=".to_string()]);
+        assert_eq!(set_ac_header(&mut mail, &header),
+                   Err(HeaderInjectError::FromMismatch {
+                           from: "bob@mail.com".to_string(),
+                           addr: "alice@mail.com".to_string(),
+                       }));
+        assert_eq!(mail.headers.len(), 3);
+
+        let mut mail = MimeMessage::new("hello".to_string());
+        assert_eq!(set_ac_header(&mut mail, &header),
+                   Err(HeaderInjectError::MissingFrom));
+        assert_eq!(set_ac_header_raw(b"Subject: hi\r\n\r\nhello", &header),
+                   Err(HeaderInjectError::MissingFrom));
+    }
+
+    #[test]
+    fn test_set_ac_header_raw() {
+        let header = account_header("alice@testsuite.autocrypt.org");
+        let file = get_file("no-autocrypt.eml");
+        let out = set_ac_header_raw(file.as_bytes(), &header).expect("failed to set header");
+        let out = String::from_utf8(out).unwrap();
+
+        // line endings and body are kept, the header goes last
+        let split = out.find("\n\n").expect("missing body");
+        assert_eq!(out[split..], file[file.find("\n\n").unwrap()..]);
+        assert!(!out.contains('\r'));
+        assert!(out[..split].ends_with(&header.to_folded().replace("\r\n", "\n")));
+        let mail = parse(&out.replace('\n', "\r\n")).expect("failed to parse");
+        assert_eq!(get_ac_header(&mail).unwrap(), Some(header.clone()));
+
+        let raw = "Subject: hi\r\nAutocrypt: addr=alice@testsuite.autocrypt.org;\r\n                    keydata=bXlrZXk=\r\nFrom: Alice\r\n <alice@testsuite.autocrypt.org>\r\n\
+                   \r\nAutocrypt: in the body\r\n";
+        let out = set_ac_header_raw(raw.as_bytes(), &header).expect("failed to set header");
+        let expected = format!("Subject: hi\r\n{}\r\nFrom: Alice\r\n \
+                                <alice@testsuite.autocrypt.org>\r\n\r\nAutocrypt: in the body\r\n",
+                               header.to_folded());
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+
+        // headers only, without a final line break
+        let out = set_ac_header_raw(b"From: alice@testsuite.autocrypt.org", &header).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   format!("From: alice@testsuite.autocrypt.org\r\n{}\r\n", header.to_folded()));
+    }
+
     #[test]
     fn test_get_effective_time() {
         let file = get_file("no-autocrypt.eml");
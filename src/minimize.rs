@@ -0,0 +1,192 @@
+use time;
+
+use pgp::{TransferablePublicKey, SignedUserId, SignedSubkey, SignatureType, SignatureTarget};
+use keydata::KeyData;
+use mime::addr_matches;
+use validate::{validate_key, latest_self_signature, latest_binding, is_revoked, is_expired};
+use errors::KeyValidationError;
+
+/// A key stripped down for use in an Autocrypt header.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct MinimizedKey {
+    /// The minimized key, ready to be used as `Header::keydata`.
+    pub keydata: KeyData,
+    /// Size of the key before minimizing, in bytes.
+    pub original_size: usize,
+}
+
+impl MinimizedKey {
+    /// Size of the minimized key, in bytes.
+    pub fn size(&self) -> usize {
+        self.keydata.as_bytes().len()
+    }
+
+    /// Size of the minimized key once Base64 encoded, in bytes.
+    pub fn encoded_size(&self) -> usize {
+        self.keydata.to_base64().len()
+    }
+}
+
+/// Strip a key down to what Autocrypt needs.
+///
+/// The result keeps the primary key, the user id matching `addr`, the most
+/// recent encryption subkey that is neither expired nor revoked, and the
+/// latest self signatures over those. Third party certifications, other user
+/// ids, user attributes and old subkeys are dropped.
+pub fn minimize_key(key: &TransferablePublicKey,
+                    addr: &str)
+                    -> Result<MinimizedKey, KeyValidationError> {
+    let primary = &key.primary;
+
+    let user_ids: Vec<&SignedUserId> = key.user_ids
+        .iter()
+        .filter(|uid| user_id_matches(&uid.user_id.as_str(), addr))
+        .collect();
+    if user_ids.is_empty() {
+        return Err(KeyValidationError::NoMatchingUserId(addr.to_string()));
+    }
+
+    let user_id = user_ids
+        .into_iter()
+        .filter_map(|uid| {
+            let sig = latest_self_signature(&uid.signatures,
+                                            primary,
+                                            SignatureTarget::UserId(&uid.user_id),
+                                            |typ| typ.is_certification());
+            sig.map(|sig| {
+                        SignedUserId {
+                            user_id: uid.user_id.clone(),
+                            signatures: vec![sig.clone()],
+                        }
+                    })
+        })
+        .next()
+        .ok_or(KeyValidationError::MissingSelfSignature)?;
+
+    let now = time::now_utc();
+    let subkey = key.subkeys
+        .iter()
+        .filter(|subkey| !is_revoked(subkey, primary))
+        .filter_map(|subkey| {
+            let binding = latest_binding(subkey, primary)?;
+
+            let can_encrypt = binding
+                .key_flags
+                .map(|flags| flags.can_encrypt())
+                .unwrap_or_else(|| subkey.key.algorithm.can_encrypt());
+
+            if can_encrypt && !is_expired(&subkey.key, binding, now) {
+                Some(SignedSubkey {
+                         key: subkey.key.clone(),
+                         signatures: vec![binding.clone()],
+                     })
+            } else {
+                None
+            }
+        })
+        .max_by_key(|subkey| subkey.key.created.to_timespec().sec)
+        .ok_or(KeyValidationError::MissingEncryptionSubkey)?;
+
+    let signatures = latest_self_signature(&key.signatures,
+                                           primary,
+                                           SignatureTarget::PrimaryKey,
+                                           |typ| typ == SignatureType::DirectKey)
+            .into_iter()
+            .cloned()
+            .collect();
+
+    let minimized = TransferablePublicKey {
+        primary: primary.clone(),
+        signatures,
+        user_ids: vec![user_id],
+        subkeys: vec![subkey],
+    };
+    validate_key(&minimized)?;
+
+    Ok(MinimizedKey {
+           keydata: KeyData::new(minimized.to_bytes()),
+           original_size: key.to_bytes().len(),
+       })
+}
+
+/// Check if a user id, either a bare address or of the form
+/// `Name <addr>`, belongs to `addr`.
+fn user_id_matches(user_id: &str, addr: &str) -> bool {
+    let email = match (user_id.rfind('<'), user_id.rfind('>')) {
+        (Some(start), Some(end)) if start < end => &user_id[start + 1..end],
+        _ => user_id,
+    };
+
+    addr_matches(email, addr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use helpers;
+
+    fn full_key() -> TransferablePublicKey {
+        let keydata: KeyData = helpers::get_file("ed25519-full.key").parse().unwrap();
+        TransferablePublicKey::from_bytes(keydata.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_minimize() {
+        let key = full_key();
+        assert_eq!(key.user_ids.len(), 2);
+        assert_eq!(key.subkeys.len(), 2);
+
+        let minimized = minimize_key(&key, "Alice@example.org").expect("failed to minimize");
+
+        // matches the output of `gpg --export-options export-minimal` with
+        // the other user id and the expired subkey filtered out
+        assert_eq!(minimized.keydata.to_base64(),
+                   "mDMEXgvhABYJKwYBBAHaRw8BAQdA2ELgSYRwPOLeL6P8DV1S8IvDT2aqG1WFsBRdhbazAMW0GUFs\
+                    aWNlIDxhbGljZUBleGFtcGxlLm9yZz6IkAQTFggAOBYhBFXE/R+ZiOfTYfrbVfzfQp3pWHuYBQJe\
+                    C+EAAhsDBQsJCAcCBhUKCQgLAgQWAgMBAh4BAheAAAoJEPzfQp3pWHuY9VAA/Ah8/VJD2mzfeGQm\
+                    5BHYjbDD68iNNJMDnOaLXQAn1+dqAQC3u1eVh5MajaZ4UTj3MuxHqBqP0rJkN/YpK9kfvENZB7g4\
+                    BGHPmYASCisGAQQBl1UBBQEBB0CseR+z6IXKttN92nNYMQRGaFJND+yjtpQOKOKa5yeoMAMBCAeI\
+                    eAQYFggAIBYhBFXE/R+ZiOfTYfrbVfzfQp3pWHuYBQJhz5mAAhsMAAoJEPzfQp3pWHuYkuMA/3d1\
+                    iaOdQafmaXdooL5BI6/Pd2tA54uN4eRYEfFDrjhYAP9U7rHwmUuwu2lB9l+0c4gdQald1S3BwnFb\
+                    toaQmZ65Ag==");
+        assert_eq!(minimized.original_size, key.to_bytes().len());
+        assert_eq!(minimized.size(), 406);
+        assert_eq!(minimized.encoded_size(), 544);
+
+        let result = TransferablePublicKey::from_bytes(minimized.keydata.as_bytes()).unwrap();
+        assert_eq!(result.user_ids[0].user_id.as_str(), "Alice <alice@example.org>");
+        assert_eq!(result.subkeys[0].key.key_id().to_hex(), "9ED0BF08A12F1DD6");
+    }
+
+    #[test]
+    fn test_minimize_other_user_id() {
+        let minimized = minimize_key(&full_key(), "alice@work.example").expect("failed to minimize");
+        let result = TransferablePublicKey::from_bytes(minimized.keydata.as_bytes()).unwrap();
+        assert_eq!(result.user_ids[0].user_id.as_str(),
+                   "Alice Work <alice@work.example>");
+    }
+
+    #[test]
+    fn test_minimize_bare_user_id() {
+        let keydata: KeyData = helpers::get_keydata("ed25519-simple.eml").parse().unwrap();
+        let key = TransferablePublicKey::from_bytes(keydata.as_bytes()).unwrap();
+
+        let minimized = minimize_key(&key, "alice@testsuite.autocrypt.org")
+            .expect("failed to minimize");
+        assert_eq!(minimized.keydata, keydata);
+    }
+
+    #[test]
+    fn test_minimize_no_matching_user_id() {
+        assert_eq!(minimize_key(&full_key(), "bob@example.org"),
+                   Err(KeyValidationError::NoMatchingUserId("bob@example.org".to_string())));
+    }
+
+    #[test]
+    fn test_minimize_no_current_subkey() {
+        let mut key = full_key();
+        key.subkeys.pop();
+        assert_eq!(minimize_key(&key, "alice@example.org"),
+                   Err(KeyValidationError::MissingEncryptionSubkey));
+    }
+}
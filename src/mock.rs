@@ -0,0 +1,505 @@
+//! A fake `CryptoBackend`, for tests. Available to other crates with the
+//! `mock` feature.
+
+use std::str;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use sha2::{Digest, Sha256};
+
+use addr::EmailAddress;
+use crypto::{CryptoBackend, KeyAlgorithm, KeyPair, Decrypted, SignatureStatus};
+use errors::{Error, KeyValidationError, Result};
+use keydata::KeyData;
+use pgp::{self, Fingerprint, PublicKey, PublicKeyAlgorithm, Signature, SignatureTarget,
+          TransferablePublicKey};
+use validate::validate_key;
+
+/// Prefix of the secret keys generated by `MockBackend`, which are followed
+/// by the public key.
+const SECRET_PREFIX: &[u8] = b"mock secret key\0";
+
+/// Signature type of positive user id certifications.
+const CERTIFICATION: u8 = 0x13;
+
+/// Signature type of subkey binding signatures.
+const SUBKEY_BINDING: u8 = 0x18;
+
+/// A deterministic `CryptoBackend` doing no actual cryptography, for tests.
+///
+/// Generated keys are derived from the address and pass `validate_key`: a
+/// signing primary key with a certified user id, and an encryption subkey
+/// with a binding signature. The signatures have a correct hash prefix, but
+/// are just the digest they sign instead of a real signature, which is what
+/// `verify_key` checks.
+///
+/// Messages are not encrypted at all, but carry the fingerprints of their
+/// recipients, and decrypt only with the secret key of one of those.
+/// Passphrase encrypted messages carry a hash of the passphrase instead.
+/// Signatures are a hash over the data and the fingerprint of the signer,
+/// so modified data is detected. The same input always produces the same
+/// output.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct MockBackend;
+
+impl MockBackend {
+    pub fn new() -> MockBackend {
+        MockBackend
+    }
+
+    /// The public key belonging to a secret key generated by this backend.
+    pub fn public_key(secret_key: &KeyData) -> Result<KeyData> {
+        let bytes = secret_key.as_bytes();
+        if !bytes.starts_with(SECRET_PREFIX) {
+            return Err(Error::crypto("not a mock secret key"));
+        }
+
+        Ok(KeyData::new(bytes[SECRET_PREFIX.len()..].to_vec()))
+    }
+}
+
+impl CryptoBackend for MockBackend {
+    fn generate_key(&self, addr: &EmailAddress, algorithm: KeyAlgorithm) -> Result<KeyPair> {
+        let seed = format!("{}:{:?}", addr.normalized(), algorithm);
+        let subkey_seed = format!("{}:subkey", seed);
+        let (primary, subkey) = match algorithm {
+            KeyAlgorithm::Ed25519 => {
+                (ecc_key(PublicKeyAlgorithm::Ed25519, &seed),
+                 ecc_key(PublicKeyAlgorithm::X25519, &subkey_seed))
+            }
+            KeyAlgorithm::Rsa(bits) => {
+                if bits < 1024 || bits % 8 != 0 {
+                    return Err(Error::crypto(format!("unsupported RSA key size {}", bits)));
+                }
+                (rsa_key(bits, &seed), rsa_key(bits, &subkey_seed))
+            }
+        };
+
+        let mut packets = Vec::new();
+        pgp::write_packet(&mut packets, 6, &primary);
+        pgp::write_packet(&mut packets, 13, format!("<{}>", addr).as_bytes());
+        pgp::write_packet(&mut packets, 14, &subkey);
+        let mut key = TransferablePublicKey::from_bytes(&packets).map_err(Error::crypto)?;
+
+        let certification = self_signature(&key.primary,
+                                           CERTIFICATION,
+                                           0x03,
+                                           SignatureTarget::UserId(&key.user_ids[0].user_id));
+        key.user_ids[0].signatures.push(certification);
+        let binding = self_signature(&key.primary,
+                                     SUBKEY_BINDING,
+                                     0x0C,
+                                     SignatureTarget::Subkey(&key.subkeys[0].key));
+        key.subkeys[0].signatures.push(binding);
+        let public = key.to_bytes();
+
+        let mut secret = SECRET_PREFIX.to_vec();
+        secret.extend_from_slice(&public);
+
+        Ok(KeyPair {
+               public: KeyData::new(public),
+               secret: KeyData::new(secret),
+           })
+    }
+
+    fn encrypt(&self,
+               data: &[u8],
+               recipients: &[KeyData],
+               signer: Option<&KeyData>)
+               -> Result<String> {
+        let mut message = Vec::new();
+        for key in recipients {
+            let fingerprint = pgp::fingerprint(key).map_err(Error::crypto)?;
+            message.extend_from_slice(format!("recipient {}\n", fingerprint.to_hex()).as_bytes());
+        }
+        if let Some(signer) = signer {
+            message.extend_from_slice(&signature(data, signer)?);
+        }
+        message.push(b'\n');
+        message.extend_from_slice(data);
+
+        Ok(armor("MESSAGE", &message))
+    }
+
+    fn decrypt(&self,
+               message: &[u8],
+               secret_key: &KeyData,
+               senders: &[KeyData])
+               -> Result<Decrypted> {
+        let message = dearmor("MESSAGE", message)?;
+        let (head, data) = split_message(&message)?;
+
+        let own = pgp::fingerprint(&MockBackend::public_key(secret_key)?)
+            .map_err(Error::crypto)?
+            .to_hex();
+        if !head.lines().any(|l| l == format!("recipient {}", own)) {
+            return Err(Error::crypto("message is not encrypted to this key"));
+        }
+
+        let signature = if head.lines().any(|l| l.starts_with("signer ")) {
+            check_signature(data, head, senders)?
+        } else {
+            SignatureStatus::Unsigned
+        };
+
+        Ok(Decrypted {
+               data: data.to_vec(),
+               signature,
+           })
+    }
+
+    fn encrypt_symmetric(&self, data: &[u8], passphrase: &str) -> Result<String> {
+        let mut message = format!("passphrase {}\n\n", passphrase_hash(passphrase)).into_bytes();
+        message.extend_from_slice(data);
+
+        Ok(armor("MESSAGE", &message))
+    }
+
+    fn decrypt_symmetric(&self, message: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+        let message = dearmor("MESSAGE", message)?;
+        let (head, data) = split_message(&message)?;
+        if !head.lines().any(|l| l == format!("passphrase {}", passphrase_hash(passphrase))) {
+            return Err(Error::crypto("wrong passphrase"));
+        }
+
+        Ok(data.to_vec())
+    }
+
+    fn sign(&self, data: &[u8], secret_key: &KeyData) -> Result<String> {
+        Ok(armor("SIGNATURE", &signature(data, secret_key)?))
+    }
+
+    fn verify(&self,
+              data: &[u8],
+              signature: &[u8],
+              senders: &[KeyData])
+              -> Result<SignatureStatus> {
+        let signature = dearmor("SIGNATURE", signature)?;
+        let signature = str::from_utf8(&signature)
+            .map_err(|_| Error::crypto("invalid mock signature"))?;
+        check_signature(data, signature, senders)
+    }
+
+    fn verify_key(&self, key: &KeyData) -> ::std::result::Result<(), KeyValidationError> {
+        let key = TransferablePublicKey::from_bytes(key.as_bytes())?;
+        validate_key(&key)?;
+
+        let primary = &key.primary;
+        let user_ids = key.user_ids.iter().flat_map(|uid| {
+            uid.signatures
+                .iter()
+                .map(move |sig| (sig, SignatureTarget::UserId(&uid.user_id)))
+        });
+        let subkeys = key.subkeys.iter().flat_map(|subkey| {
+            subkey
+                .signatures
+                .iter()
+                .map(move |sig| (sig, SignatureTarget::Subkey(&subkey.key)))
+        });
+        let mut self_signatures = key.signatures
+            .iter()
+            .map(|sig| (sig, SignatureTarget::PrimaryKey))
+            .chain(user_ids)
+            .chain(subkeys)
+            .filter(|&(sig, _)| sig.is_issued_by(primary));
+        if self_signatures.all(|(sig, target)| is_valid_self_signature(sig, primary, target)) {
+            Ok(())
+        } else {
+            Err(KeyValidationError::Unverified("forged mock signature".to_string()))
+        }
+    }
+}
+
+/// Derive `len` bytes from `seed`.
+fn expand(seed: &str, len: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(len);
+    let mut counter = 0u32;
+    while out.len() < len {
+        let mut hasher = Sha256::new();
+        hasher.update(counter.to_be_bytes());
+        hasher.update(seed.as_bytes());
+        out.extend_from_slice(&hasher.finalize());
+        counter += 1;
+    }
+    out.truncate(len);
+
+    out
+}
+
+/// The body of a version 4 public key packet of an algorithm with a 32 byte
+/// native key, such as Ed25519 or X25519.
+fn ecc_key(algorithm: PublicKeyAlgorithm, seed: &str) -> Vec<u8> {
+    let mut body = vec![4, 0, 0, 0, 0, u8::from(algorithm)];
+    body.extend_from_slice(&expand(seed, 32));
+
+    body
+}
+
+/// The body of a version 4 RSA public key packet, with a modulus of `bits`.
+fn rsa_key(bits: usize, seed: &str) -> Vec<u8> {
+    let mut n = expand(seed, bits / 8);
+    n[0] |= 0x80;
+
+    let mut body = vec![4, 0, 0, 0, 0, u8::from(PublicKeyAlgorithm::RSA)];
+    body.extend_from_slice(&(bits as u16).to_be_bytes());
+    body.extend_from_slice(&n);
+    body.extend_from_slice(&[0, 17, 1, 0, 1]);
+
+    body
+}
+
+/// A self signature of `primary` over `target`, carrying the given key
+/// flags. It hashes with SHA-256, and instead of a real signature contains
+/// the digest as a single MPI.
+fn self_signature(primary: &PublicKey, typ: u8, flags: u8, target: SignatureTarget) -> Signature {
+    let mut hashed = vec![5, 2, 0, 0, 0, 0, 2, 27, flags, 22, 33, 4];
+    hashed.extend_from_slice(primary.fingerprint().as_bytes());
+
+    let mut body = vec![4, typ, u8::from(primary.algorithm), 8];
+    body.extend_from_slice(&(hashed.len() as u16).to_be_bytes());
+    body.extend_from_slice(&hashed);
+    // no unhashed subpackets, followed by the hash prefix
+    body.extend_from_slice(&[0, 0, 0, 0]);
+
+    let digest = pgp::parse_signature(&body)
+        .ok()
+        .and_then(|sig| sig.digest(primary, target))
+        .expect("SHA-256 signature");
+    body.truncate(body.len() - 2);
+    body.extend_from_slice(&digest[..2]);
+    body.extend_from_slice(&[1, 0]);
+    body.extend_from_slice(&digest);
+
+    pgp::parse_signature(&body).expect("valid signature")
+}
+
+/// Whether `sig` is a self signature as made by `self_signature`.
+fn is_valid_self_signature(sig: &Signature, primary: &PublicKey, target: SignatureTarget) -> bool {
+    match sig.digest(primary, target) {
+        Some(digest) => {
+            let mut signature = vec![1, 0];
+            signature.extend_from_slice(&digest);
+            sig.is_self_signature(primary, target) && sig.body().ends_with(&signature)
+        }
+        None => false,
+    }
+}
+
+/// Split a dearmored mock message into its head and the data.
+fn split_message(message: &[u8]) -> Result<(&str, &[u8])> {
+    let split = message
+        .windows(2)
+        .position(|w| w == b"\n\n")
+        .ok_or_else(|| Error::crypto("invalid mock message"))?;
+    let head = str::from_utf8(&message[..split + 1])
+        .map_err(|_| Error::crypto("invalid mock message"))?;
+
+    Ok((head, &message[split + 2..]))
+}
+
+fn passphrase_hash(passphrase: &str) -> String {
+    Sha256::digest(passphrase.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn digest(data: &[u8], signer: &Fingerprint) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(signer.as_bytes());
+    hasher.update(data);
+    hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn signature(data: &[u8], secret_key: &KeyData) -> Result<Vec<u8>> {
+    let signer = pgp::fingerprint(&MockBackend::public_key(secret_key)?).map_err(Error::crypto)?;
+    let signature = format!("signer {}\nsignature {}\n", signer.to_hex(), digest(data, &signer));
+
+    Ok(signature.into_bytes())
+}
+
+fn check_signature(data: &[u8], signature: &str, senders: &[KeyData]) -> Result<SignatureStatus> {
+    let field = |name: &str| {
+        signature
+            .lines()
+            .find(|l| l.starts_with(name) && l[name.len()..].starts_with(' '))
+            .map(|l| &l[name.len() + 1..])
+            .ok_or_else(|| Error::crypto("invalid mock signature"))
+    };
+    let signer = field("signer")?;
+    let hash = field("signature")?;
+
+    for key in senders {
+        let fingerprint = pgp::fingerprint(key).map_err(Error::crypto)?;
+        if fingerprint.to_hex() == signer {
+            if digest(data, &fingerprint) == hash {
+                return Ok(SignatureStatus::Valid(fingerprint));
+            }
+            return Ok(SignatureStatus::Invalid);
+        }
+    }
+
+    let bytes = (0..signer.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(signer.get(i..i + 2).unwrap_or(""), 16))
+        .collect::<::std::result::Result<Vec<u8>, _>>()
+        .map_err(|_| Error::crypto("invalid mock signature"))?;
+    let mut fingerprint = [0u8; 20];
+    if bytes.len() != fingerprint.len() {
+        return Err(Error::crypto("invalid mock signature"));
+    }
+    fingerprint.copy_from_slice(&bytes);
+
+    Ok(SignatureStatus::UnknownKey(Fingerprint::V4(fingerprint).key_id()))
+}
+
+fn armor(kind: &str, data: &[u8]) -> String {
+    let mut armored = format!("-----BEGIN PGP {}-----\n\n", kind);
+    for line in STANDARD.encode(data).as_bytes().chunks(64) {
+        armored.push_str(str::from_utf8(line).expect("base64 is ascii"));
+        armored.push('\n');
+    }
+    armored.push_str(&format!("-----END PGP {}-----\n", kind));
+
+    armored
+}
+
+fn dearmor(kind: &str, data: &[u8]) -> Result<Vec<u8>> {
+    let invalid = || Error::crypto(format!("invalid mock {}", kind.to_lowercase()));
+    let text = str::from_utf8(data).map_err(|_| invalid())?;
+    let begin = format!("-----BEGIN PGP {}-----", kind);
+    let end = format!("-----END PGP {}-----", kind);
+
+    let mut lines = text.lines().map(str::trim).skip_while(|l| l.is_empty());
+    if lines.next() != Some(&begin[..]) {
+        return Err(invalid());
+    }
+    let encoded: String = lines
+        .skip_while(|l| !l.is_empty())
+        .take_while(|l| *l != end)
+        .collect();
+
+    STANDARD.decode(encoded).map_err(|_| invalid())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use helpers::addr;
+
+    #[test]
+    fn test_generate_key() {
+        let backend = MockBackend::new();
+        let pair = backend.generate_key(&addr("alice@mail.com"), KeyAlgorithm::Ed25519).unwrap();
+        assert_eq!(pair,
+                   backend.generate_key(&addr("alice@mail.com"), KeyAlgorithm::Ed25519).unwrap());
+        let other = backend.generate_key(&addr("Alice@Mail.com"), KeyAlgorithm::Ed25519).unwrap();
+        assert_eq!(::pgp::fingerprint(&other.public), ::pgp::fingerprint(&pair.public));
+        assert_eq!(MockBackend::public_key(&pair.secret).unwrap(), pair.public);
+        assert!(MockBackend::public_key(&pair.public).is_err());
+
+        let key = ::pgp::TransferablePublicKey::from_bytes(pair.public.as_bytes()).unwrap();
+        assert_eq!(key.primary.algorithm, ::pgp::PublicKeyAlgorithm::Ed25519);
+        assert_eq!(key.user_ids[0].user_id.as_str(), "<alice@mail.com>");
+
+        let rsa = backend.generate_key(&addr("alice@mail.com"), KeyAlgorithm::Rsa(3072)).unwrap();
+        let key = ::pgp::TransferablePublicKey::from_bytes(rsa.public.as_bytes()).unwrap();
+        assert_eq!(key.primary.bits, Some(3072));
+        assert!(rsa != pair);
+        assert!(backend.generate_key(&addr("alice@mail.com"), KeyAlgorithm::Rsa(512)).is_err());
+    }
+
+    #[test]
+    fn test_generated_key_is_valid() {
+        let backend = MockBackend::new();
+        for &algorithm in &[KeyAlgorithm::Ed25519, KeyAlgorithm::Rsa(2048)] {
+            let pair = backend.generate_key(&addr("alice@mail.com"), algorithm).unwrap();
+            let key = TransferablePublicKey::from_bytes(pair.public.as_bytes()).unwrap();
+            ::validate_key(&key).unwrap();
+            assert_eq!(key.primary_key_flags().map(|f| f.can_sign()), Some(true));
+            assert_eq!(key.encryption_subkeys().len(), 1);
+            backend.verify_key(&pair.public).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_verify_forged_key() {
+        let backend = MockBackend::new();
+        let pair = backend.generate_key(&addr("alice@mail.com"), KeyAlgorithm::Ed25519).unwrap();
+        let mut bytes = pair.public.as_bytes().to_vec();
+
+        // the last byte of the subkey binding signature
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        let forged = KeyData::new(bytes);
+        let key = TransferablePublicKey::from_bytes(forged.as_bytes()).unwrap();
+        ::validate_key(&key).unwrap();
+        assert_eq!(backend.verify_key(&forged),
+                   Err(KeyValidationError::Unverified("forged mock signature".to_string())));
+
+        assert_eq!(backend.verify_key(&KeyData::new(b"garbage".to_vec())),
+                   Err(KeyValidationError::Parse(::errors::KeyParseError::InvalidPacketHeader)));
+    }
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let backend = MockBackend::new();
+        let alice = backend.generate_key(&addr("alice@mail.com"), KeyAlgorithm::Ed25519).unwrap();
+        let bob = backend.generate_key(&addr("bob@mail.com"), KeyAlgorithm::Ed25519).unwrap();
+        let carol = backend.generate_key(&addr("carol@mail.com"), KeyAlgorithm::Ed25519).unwrap();
+
+        let recipients = vec![bob.public.clone()];
+        let senders = vec![alice.public.clone()];
+
+        let armored = backend.encrypt(b"hello", &recipients, Some(&alice.secret)).unwrap();
+        assert!(armored.starts_with("-----BEGIN PGP MESSAGE-----\n"));
+        assert_eq!(armored,
+                   backend.encrypt(b"hello", &recipients, Some(&alice.secret)).unwrap());
+
+        let decrypted = backend.decrypt(armored.as_bytes(), &bob.secret, &senders).unwrap();
+        assert_eq!(decrypted.data, b"hello");
+        assert_eq!(decrypted.signature,
+                   SignatureStatus::Valid(::pgp::fingerprint(&alice.public).unwrap()));
+
+        let decrypted = backend.decrypt(armored.as_bytes(), &bob.secret, &[]).unwrap();
+        let alice_id = ::pgp::fingerprint(&alice.public).unwrap().key_id();
+        assert_eq!(decrypted.signature, SignatureStatus::UnknownKey(alice_id));
+
+        let err = backend.decrypt(armored.as_bytes(), &carol.secret, &[]).unwrap_err();
+        assert_eq!(err.to_string(), "Crypto error: message is not encrypted to this key");
+
+        let unsigned = backend.encrypt(b"hello", &recipients, None).unwrap();
+        let decrypted = backend.decrypt(unsigned.as_bytes(), &bob.secret, &[]).unwrap();
+        assert_eq!(decrypted.signature, SignatureStatus::Unsigned);
+    }
+
+    #[test]
+    fn test_symmetric_roundtrip() {
+        let backend = MockBackend::new();
+        let alice = backend.generate_key(&addr("alice@mail.com"), KeyAlgorithm::Ed25519).unwrap();
+
+        let message = backend.encrypt_symmetric(b"hello", "1234").unwrap();
+        assert!(message.starts_with("-----BEGIN PGP MESSAGE-----\n"));
+        assert_eq!(backend.decrypt_symmetric(message.as_bytes(), "1234").unwrap(), b"hello");
+
+        let err = backend.decrypt_symmetric(message.as_bytes(), "4321").unwrap_err();
+        assert_eq!(err.to_string(), "Crypto error: wrong passphrase");
+        assert!(backend.decrypt(message.as_bytes(), &alice.secret, &[]).is_err());
+    }
+
+    #[test]
+    fn test_sign_verify() {
+        let backend = MockBackend::new();
+        let alice = backend.generate_key(&addr("alice@mail.com"), KeyAlgorithm::Ed25519).unwrap();
+
+        let signature = backend.sign(b"hello", &alice.secret).unwrap();
+        assert!(signature.starts_with("-----BEGIN PGP SIGNATURE-----\n"));
+        let senders = [alice.public.clone()];
+        assert_eq!(backend.verify(b"hello", signature.as_bytes(), &senders).unwrap(),
+                   SignatureStatus::Valid(::pgp::fingerprint(&alice.public).unwrap()));
+        assert_eq!(backend.verify(b"hello!", signature.as_bytes(), &senders).unwrap(),
+                   SignatureStatus::Invalid);
+        assert!(backend.verify(b"hello", b"garbage", &senders).is_err());
+        assert!(backend.sign(b"hello", &alice.public).is_err());
+    }
+}
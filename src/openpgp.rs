@@ -0,0 +1,738 @@
+//! A `CryptoBackend` built on rPGP, a pure Rust OpenPGP implementation,
+//! enabled by the `openpgp` feature.
+//!
+//! Secret keys must be unprotected, which is how Autocrypt stores and
+//! transfers them.
+
+use std::convert::TryFrom;
+use std::io::Read;
+
+use rand::thread_rng;
+use rpgp::composed::{ArmorOptions, Deserializable, DetachedSignature, EncryptionCaps, KeyType,
+                     Message, MessageBuilder, SecretKeyParamsBuilder, SignedPublicKey,
+                     SignedPublicSubKey, SignedSecretKey, SubkeyParamsBuilder};
+use rpgp::crypto::ecc_curve::ECCCurve;
+use rpgp::crypto::hash::HashAlgorithm;
+use rpgp::crypto::sym::SymmetricKeyAlgorithm;
+use rpgp::packet::{PublicKey, PublicSubkey, Signature, SignatureType, SubpacketData};
+use rpgp::ser::Serialize;
+use rpgp::types::{KeyDetails, Password, StringToKey, Tag, Timestamp, VerifyingKey};
+
+use addr::EmailAddress;
+use crypto::{CryptoBackend, KeyAlgorithm, KeyPair, Decrypted, SignatureStatus};
+use errors::{Error, KeyValidationError, Result};
+use keydata::KeyData;
+use pgp::{self, Fingerprint, KeyId, TransferablePublicKey};
+use validate::validate_key;
+
+/// The smallest RSA key generated, in bits.
+const MIN_RSA_BITS: usize = 2048;
+
+/// The largest plaintext read from a message, 64 MiB. A small compressed
+/// message can expand to far more than that.
+const MAX_MESSAGE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// The iteration count of passphrase encrypted messages, 4 MiB. The
+/// passphrase of an Autocrypt Setup Message has more than 120 bits of
+/// entropy, so this is about compatibility rather than strength.
+const S2K_COUNT: u8 = 0xC0;
+
+/// A `CryptoBackend` using rPGP, without GnuPG or any other system library.
+///
+/// Generated keys are an Ed25519 primary key with a Curve25519 encryption
+/// subkey, or an RSA primary key of at least 2048 bits with an RSA
+/// encryption subkey. Messages are encrypted with AES-256 and signatures
+/// use SHA-256. Passphrase encrypted messages use AES-128, as the
+/// Autocrypt Setup Message requires, with the key derived by the iterated
+/// and salted S2K using SHA-256.
+///
+/// Signatures are only accepted from keys allowed to sign data: a primary
+/// key whose self-signature says so, or a subkey with a signing binding and
+/// a back signature. Revoked and expired keys are never used.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct OpenPgpBackend;
+
+impl OpenPgpBackend {
+    pub fn new() -> OpenPgpBackend {
+        OpenPgpBackend
+    }
+
+    /// The transferable public key belonging to a transferable secret key.
+    pub fn public_key(secret_key: &KeyData) -> Result<KeyData> {
+        let key = parse_secret_key(secret_key)?;
+        let public = key.to_public_key().to_bytes().map_err(Error::crypto)?;
+
+        Ok(KeyData::new(public))
+    }
+}
+
+impl CryptoBackend for OpenPgpBackend {
+    fn generate_key(&self, addr: &EmailAddress, algorithm: KeyAlgorithm) -> Result<KeyPair> {
+        let (primary, subkey) = match algorithm {
+            KeyAlgorithm::Ed25519 => {
+                (KeyType::Ed25519Legacy, KeyType::ECDH(ECCCurve::Curve25519Legacy))
+            }
+            KeyAlgorithm::Rsa(bits) if bits < MIN_RSA_BITS => {
+                return Err(Error::crypto(format!("RSA keys must have at least {} bits",
+                                                 MIN_RSA_BITS)))
+            }
+            KeyAlgorithm::Rsa(bits) => {
+                let bits = u32::try_from(bits).map_err(Error::crypto)?;
+                (KeyType::Rsa(bits), KeyType::Rsa(bits))
+            }
+        };
+
+        let subkey = SubkeyParamsBuilder::default()
+            .key_type(subkey)
+            .can_encrypt(EncryptionCaps::All)
+            .build()
+            .map_err(Error::crypto)?;
+        let params = SecretKeyParamsBuilder::default()
+            .key_type(primary)
+            .can_certify(true)
+            .can_sign(true)
+            .primary_user_id(format!("<{}>", addr))
+            .preferred_symmetric_algorithms(vec![SymmetricKeyAlgorithm::AES256,
+                                                 SymmetricKeyAlgorithm::AES192,
+                                                 SymmetricKeyAlgorithm::AES128]
+                                                    .into())
+            .preferred_hash_algorithms(vec![HashAlgorithm::Sha256, HashAlgorithm::Sha512].into())
+            .subkeys(vec![subkey])
+            .build()
+            .map_err(Error::crypto)?;
+        let secret = params.generate(thread_rng()).map_err(Error::crypto)?;
+
+        Ok(KeyPair {
+               public: KeyData::new(secret.to_public_key().to_bytes().map_err(Error::crypto)?),
+               secret: KeyData::new(secret.to_bytes().map_err(Error::crypto)?),
+           })
+    }
+
+    fn encrypt(&self,
+               data: &[u8],
+               recipients: &[KeyData],
+               signer: Option<&KeyData>)
+               -> Result<String> {
+        if recipients.is_empty() {
+            return Err(Error::crypto("no recipients"));
+        }
+        let recipients = recipients
+            .iter()
+            .map(parse_public_key)
+            .collect::<Result<Vec<_>>>()?;
+        let signer = match signer {
+            Some(signer) => Some(parse_secret_key(signer)?),
+            None => None,
+        };
+
+        let mut rng = thread_rng();
+        let mut builder = MessageBuilder::from_bytes("", data.to_vec())
+            .seipd_v1(&mut rng, SymmetricKeyAlgorithm::AES256);
+        for key in &recipients {
+            builder
+                .encrypt_to_key(&mut rng, encryption_subkey(key)?)
+                .map_err(Error::crypto)?;
+        }
+        if let Some(ref signer) = signer {
+            builder.sign(&signer.primary_key, Password::empty(), HashAlgorithm::Sha256);
+        }
+
+        builder
+            .to_armored_string(&mut rng, ArmorOptions::default())
+            .map_err(Error::crypto)
+    }
+
+    fn decrypt(&self,
+               message: &[u8],
+               secret_key: &KeyData,
+               senders: &[KeyData])
+               -> Result<Decrypted> {
+        let key = parse_secret_key(secret_key)?;
+        let (message, _) = Message::from_reader(message).map_err(Error::crypto)?;
+        let message = message
+            .decrypt(&Password::empty(), &key)
+            .map_err(Error::crypto)?;
+        let (data, signature) = read_message(message, senders, MAX_MESSAGE_SIZE)?;
+
+        Ok(Decrypted { data, signature })
+    }
+
+    fn encrypt_symmetric(&self, data: &[u8], passphrase: &str) -> Result<String> {
+        let mut rng = thread_rng();
+        let s2k = StringToKey::new_iterated(&mut rng, HashAlgorithm::Sha256, S2K_COUNT);
+        let mut builder = MessageBuilder::from_bytes("", data.to_vec())
+            .seipd_v1(&mut rng, SymmetricKeyAlgorithm::AES128);
+        builder
+            .encrypt_with_password(s2k, &Password::from(passphrase))
+            .map_err(Error::crypto)?;
+
+        builder
+            .to_armored_string(&mut rng, ArmorOptions::default())
+            .map_err(Error::crypto)
+    }
+
+    fn decrypt_symmetric(&self, message: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+        let (message, _) = Message::from_reader(message).map_err(Error::crypto)?;
+        let message = message
+            .decrypt_with_password(&Password::from(passphrase))
+            .map_err(Error::crypto)?;
+
+        Ok(read_message(message, &[], MAX_MESSAGE_SIZE)?.0)
+    }
+
+    fn sign(&self, data: &[u8], secret_key: &KeyData) -> Result<String> {
+        let key = parse_secret_key(secret_key)?;
+        let signature = DetachedSignature::sign_binary_data(thread_rng(),
+                                                            &key.primary_key,
+                                                            &Password::empty(),
+                                                            HashAlgorithm::Sha256,
+                                                            data)
+                .map_err(Error::crypto)?;
+
+        signature
+            .to_armored_string(ArmorOptions::default())
+            .map_err(Error::crypto)
+    }
+
+    fn verify(&self,
+              data: &[u8],
+              signature: &[u8],
+              senders: &[KeyData])
+              -> Result<SignatureStatus> {
+        let (signature, _) = DetachedSignature::from_reader_single(signature)
+            .map_err(Error::crypto)?;
+        let signature = signature.signature;
+
+        signature_status(&[&signature],
+                         senders,
+                         |_, signer| signer.verify(&signature, data))
+    }
+
+    fn verify_key(&self, key: &KeyData) -> ::std::result::Result<(), KeyValidationError> {
+        let algorithm = {
+            let key = TransferablePublicKey::from_bytes(key.as_bytes())?;
+            validate_key(&key)?;
+            key.primary.algorithm
+        };
+        let key = SignedPublicKey::from_bytes(key.as_bytes())
+            .map_err(|err| KeyValidationError::Unverified(err.to_string()))?;
+
+        if is_revoked(&key) {
+            return Err(KeyValidationError::PrimaryRevoked);
+        }
+        let certification = primary_certification(&key)
+            .ok_or(KeyValidationError::MissingSelfSignature)?;
+        if is_expired(certification, &key.primary_key) {
+            return Err(KeyValidationError::PrimaryExpired);
+        }
+        let has_flags = certification.config().is_some_and(|config| {
+            config
+                .hashed_subpackets()
+                .any(|p| matches!(p.data, SubpacketData::KeyFlags(_)))
+        });
+        if has_flags && !certification.key_flags().sign() {
+            return Err(KeyValidationError::PrimaryCannotSign(algorithm));
+        }
+        encryption_subkey(&key).map_err(|_| KeyValidationError::MissingEncryptionSubkey)?;
+
+        Ok(())
+    }
+}
+
+/// A key that may issue data signatures.
+#[derive(Clone, Copy)]
+enum Signer<'a> {
+    Primary(&'a PublicKey),
+    Subkey(&'a PublicSubkey),
+}
+
+impl<'a> Signer<'a> {
+    fn key(&self) -> &'a dyn VerifyingKey {
+        match *self {
+            Signer::Primary(key) => key,
+            Signer::Subkey(key) => key,
+        }
+    }
+
+    fn verify(&self, signature: &Signature, data: &[u8]) -> bool {
+        match *self {
+            Signer::Primary(key) => signature.verify(key, data).is_ok(),
+            Signer::Subkey(key) => signature.verify(key, data).is_ok(),
+        }
+    }
+}
+
+fn parse_public_key(key: &KeyData) -> Result<SignedPublicKey> {
+    SignedPublicKey::from_bytes(key.as_bytes()).map_err(Error::crypto)
+}
+
+fn parse_secret_key(key: &KeyData) -> Result<SignedSecretKey> {
+    SignedSecretKey::from_bytes(key.as_bytes()).map_err(Error::crypto)
+}
+
+/// Read the literal data of a decrypted message, along with the status of
+/// its signatures. Fails if the data, after decompression, is larger than
+/// `limit` bytes.
+fn read_message(message: Message, senders: &[KeyData], limit: u64)
+                -> Result<(Vec<u8>, SignatureStatus)> {
+    let mut message = message.decompress().map_err(Error::crypto)?;
+    while message.is_compressed() {
+        message = message.decompress().map_err(Error::crypto)?;
+    }
+    if message.is_encrypted() {
+        return Err(Error::crypto("nested encrypted messages are not supported"));
+    }
+
+    let mut data = Vec::new();
+    (&mut message)
+        .take(limit + 1)
+        .read_to_end(&mut data)
+        .map_err(Error::crypto)?;
+    if data.len() as u64 > limit {
+        return Err(Error::crypto("message is too large"));
+    }
+
+    let signature = match message {
+        Message::Signed { ref reader, .. } => {
+            let signatures: Vec<_> = (0..reader.num_signatures())
+                .filter_map(|i| reader.signature(i))
+                .collect();
+            signature_status(&signatures, senders, |i, signer| {
+                message.verify_nested_explicit(i, signer.key()).is_ok()
+            })?
+        }
+        _ => SignatureStatus::Unsigned,
+    };
+
+    Ok((data, signature))
+}
+
+/// The combined status of `signatures`: valid if any of them is, otherwise
+/// the status of the first one. `verify` checks the signature at an index
+/// against a key.
+fn signature_status<F>(signatures: &[&Signature], senders: &[KeyData], verify: F)
+                       -> Result<SignatureStatus>
+    where F: Fn(usize, Signer) -> bool
+{
+    let senders = senders
+        .iter()
+        .map(|key| Ok((pgp::fingerprint(key).map_err(Error::crypto)?, parse_public_key(key)?)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut status = SignatureStatus::Unsigned;
+    for (i, signature) in signatures.iter().enumerate() {
+        let current = verify_signature(i, signature, &senders, &verify);
+        match current {
+            SignatureStatus::Valid(_) => return Ok(current),
+            _ if status == SignatureStatus::Unsigned => status = current,
+            _ => {}
+        }
+    }
+
+    Ok(status)
+}
+
+fn verify_signature<F>(index: usize,
+                       signature: &Signature,
+                       senders: &[(Fingerprint, SignedPublicKey)],
+                       verify: &F)
+                       -> SignatureStatus
+    where F: Fn(usize, Signer) -> bool
+{
+    for (fingerprint, key) in senders {
+        let signer = signing_keys(key)
+            .into_iter()
+            .find(|signer| is_issued_by(signature, signer.key()));
+        if let Some(signer) = signer {
+            return if verify(index, signer) {
+                       SignatureStatus::Valid(fingerprint.clone())
+                   } else {
+                       SignatureStatus::Invalid
+                   };
+        }
+
+        // made by a key of the sender which may not sign data
+        let mut keys = Some(&key.primary_key as &dyn KeyDetails)
+            .into_iter()
+            .chain(key.public_subkeys.iter().map(|k| &k.key as &dyn KeyDetails));
+        if keys.any(|k| is_issued_by(signature, k)) {
+            return SignatureStatus::Invalid;
+        }
+    }
+
+    match issuer_key_id(signature) {
+        Some(id) => SignatureStatus::UnknownKey(id),
+        None => SignatureStatus::Invalid,
+    }
+}
+
+fn is_issued_by(signature: &Signature, key: &dyn KeyDetails) -> bool {
+    signature
+        .issuer_fingerprint()
+        .iter()
+        .any(|&fpr| *fpr == key.fingerprint()) ||
+    signature
+        .issuer_key_id()
+        .iter()
+        .any(|&id| *id == key.legacy_key_id())
+}
+
+/// The key id of the issuer of a signature, from either the issuer or the
+/// issuer fingerprint subpacket.
+fn issuer_key_id(signature: &Signature) -> Option<KeyId> {
+    if let Some(id) = signature.issuer_key_id().first() {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(id.as_ref());
+        return Some(KeyId::new(bytes));
+    }
+
+    let fpr = signature.issuer_fingerprint().first()?.as_bytes();
+    let fpr = match fpr.len() {
+        20 => Fingerprint::V4(<[u8; 20]>::try_from(fpr).ok()?),
+        32 => Fingerprint::V6(<[u8; 32]>::try_from(fpr).ok()?),
+        _ => return None,
+    };
+    Some(fpr.key_id())
+}
+
+/// The keys of `key` allowed to sign data: the primary key if its latest
+/// user id certification says so, and the subkeys whose latest binding
+/// says so and carries a valid back signature.
+fn signing_keys(key: &SignedPublicKey) -> Vec<Signer<'_>> {
+    let primary = &key.primary_key;
+    let mut keys = Vec::new();
+    if is_revoked(key) {
+        return keys;
+    }
+
+    if let Some(sig) = primary_certification(key) {
+        if sig.key_flags().sign() && !is_expired(sig, primary) {
+            keys.push(Signer::Primary(primary));
+        }
+    }
+
+    for subkey in &key.public_subkeys {
+        if let Some(binding) = subkey_binding(primary, subkey) {
+            let backsig = binding
+                .embedded_signature()
+                .is_some_and(|sig| sig.verify_primary_key_binding(&subkey.key, primary).is_ok());
+            if binding.key_flags().sign() && backsig {
+                keys.push(Signer::Subkey(&subkey.key));
+            }
+        }
+    }
+
+    keys
+}
+
+/// The latest user id certification of the primary key that verifies.
+fn primary_certification(key: &SignedPublicKey) -> Option<&Signature> {
+    let primary = &key.primary_key;
+    key.details
+        .users
+        .iter()
+        .flat_map(|user| {
+            user.signatures
+                .iter()
+                .filter(move |sig| {
+                            sig.typ() != Some(SignatureType::CertRevocation) &&
+                            sig.verify_certification(primary, Tag::UserId, &user.id).is_ok()
+                        })
+        })
+        .max_by_key(|sig| sig.created())
+}
+
+/// The newest subkey of `key` allowed to encrypt communications or storage.
+fn encryption_subkey(key: &SignedPublicKey) -> Result<&PublicSubkey> {
+    let no_key = || Error::crypto("key has no usable encryption subkey");
+    if is_revoked(key) {
+        return Err(no_key());
+    }
+
+    key.public_subkeys
+        .iter()
+        .filter(|subkey| match subkey_binding(&key.primary_key, subkey) {
+                    Some(binding) => {
+                        let flags = binding.key_flags();
+                        flags.encrypt_comms() || flags.encrypt_storage()
+                    }
+                    None => false,
+                })
+        .max_by_key(|subkey| subkey.key.created_at())
+        .map(|subkey| &subkey.key)
+        .ok_or_else(no_key)
+}
+
+/// Whether the primary key carries a valid revocation signature.
+fn is_revoked(key: &SignedPublicKey) -> bool {
+    key.details
+        .revocation_signatures
+        .iter()
+        .any(|sig| sig.verify_key(&key.primary_key).is_ok())
+}
+
+/// The latest valid binding signature of a subkey, unless the subkey is
+/// revoked or expired.
+fn subkey_binding<'a>(primary: &PublicKey, subkey: &'a SignedPublicSubKey)
+                      -> Option<&'a Signature> {
+    let verified = |typ| {
+        subkey
+            .signatures
+            .iter()
+            .filter(move |sig| {
+                        sig.typ() == Some(typ) &&
+                        sig.verify_subkey_binding(primary, &subkey.key).is_ok()
+                    })
+    };
+    if verified(SignatureType::SubkeyRevocation).next().is_some() {
+        return None;
+    }
+
+    let binding = verified(SignatureType::SubkeyBinding).max_by_key(|sig| sig.created())?;
+    if is_expired(binding, &subkey.key) {
+        None
+    } else {
+        Some(binding)
+    }
+}
+
+/// Whether the key expiration time set by `sig` has passed.
+fn is_expired(sig: &Signature, key: &dyn KeyDetails) -> bool {
+    match sig.key_expiration_time() {
+        Some(validity) if validity.as_secs() != 0 => {
+            let expires = u64::from(key.created_at().as_secs()) + u64::from(validity.as_secs());
+            expires <= u64::from(Timestamp::now().as_secs())
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use helpers::{addr, get_file, get_keydata};
+    use pgp::TransferablePublicKey;
+    use rpgp::armor::Dearmor;
+    use rpgp::types::CompressionAlgorithm;
+
+    const PLAINTEXT: &[u8] = b"Hello Autocrypt!\n";
+    const PASSPHRASE: &str = "1742-0185-6197-1303-7016-8412-3581-4441-0597";
+
+    fn fixture_key(name: &str) -> KeyData {
+        KeyData::from_base64(&get_file(name)).unwrap()
+    }
+
+    fn alice() -> KeyData {
+        fixture_key("ed25519-secret.key")
+    }
+
+    fn bob() -> KeyData {
+        fixture_key("rsa2048-secret.key")
+    }
+
+    fn public(secret: &KeyData) -> KeyData {
+        OpenPgpBackend::public_key(secret).unwrap()
+    }
+
+    #[test]
+    fn test_public_key() {
+        let key = TransferablePublicKey::from_bytes(public(&alice()).as_bytes()).unwrap();
+        assert_eq!(key.fingerprint().to_hex(), "E9B48934C0E1DD2235EF37077CCE618DB7D33AAA");
+        assert_eq!(key.user_ids[0].user_id.as_str(), "<alice@autocrypt.example>");
+        assert_eq!(key.encryption_subkeys()[0].key.key_id().to_hex(), "810ECAB271225AC7");
+
+        let key = TransferablePublicKey::from_bytes(public(&bob()).as_bytes()).unwrap();
+        assert_eq!(key.fingerprint().to_hex(), "32709728579F471A6C38DA31E8C9EF8E62328E67");
+    }
+
+    #[test]
+    fn test_decrypt_fixtures() {
+        let backend = OpenPgpBackend::new();
+
+        let message = get_file("message-to-ed25519.asc");
+        let decrypted = backend
+            .decrypt(message.as_bytes(), &alice(), &[public(&bob())])
+            .unwrap();
+        assert_eq!(decrypted.data, PLAINTEXT);
+        assert_eq!(decrypted.signature,
+                   SignatureStatus::Valid(pgp::fingerprint(&public(&bob())).unwrap()));
+
+        let message = get_file("message-to-rsa2048.asc");
+        let decrypted = backend
+            .decrypt(message.as_bytes(), &bob(), &[public(&bob()), public(&alice())])
+            .unwrap();
+        assert_eq!(decrypted.data, PLAINTEXT);
+        assert_eq!(decrypted.signature,
+                   SignatureStatus::Valid(pgp::fingerprint(&public(&alice())).unwrap()));
+
+        // without the key of the sender
+        let decrypted = backend.decrypt(message.as_bytes(), &bob(), &[]).unwrap();
+        assert_eq!(decrypted.signature,
+                   SignatureStatus::UnknownKey(pgp::fingerprint(&public(&alice()))
+                                                   .unwrap()
+                                                   .key_id()));
+
+        // not encrypted to alice
+        assert!(backend.decrypt(message.as_bytes(), &alice(), &[]).is_err());
+    }
+
+    #[test]
+    fn test_verify_fixtures() {
+        let backend = OpenPgpBackend::new();
+        let senders = [public(&alice()), public(&bob())];
+
+        for &(name, key) in &[("signature-ed25519.asc", &senders[0]),
+                              ("signature-rsa2048.asc", &senders[1])] {
+            let signature = get_file(name);
+            assert_eq!(backend.verify(PLAINTEXT, signature.as_bytes(), &senders).unwrap(),
+                       SignatureStatus::Valid(pgp::fingerprint(key).unwrap()));
+            assert_eq!(backend
+                           .verify(b"Hello Autocrypt?\n", signature.as_bytes(), &senders)
+                           .unwrap(),
+                       SignatureStatus::Invalid);
+        }
+    }
+
+    #[test]
+    fn test_decrypt_symmetric_fixture() {
+        let backend = OpenPgpBackend::new();
+        let message = get_file("symmetric.asc");
+        assert_eq!(backend.decrypt_symmetric(message.as_bytes(), PASSPHRASE).unwrap(),
+                   PLAINTEXT);
+        assert!(backend.decrypt_symmetric(message.as_bytes(), "1234").is_err());
+    }
+
+    #[test]
+    fn test_symmetric_roundtrip() {
+        let backend = OpenPgpBackend::new();
+        let message = backend.encrypt_symmetric(PLAINTEXT, PASSPHRASE).unwrap();
+        assert!(message.starts_with("-----BEGIN PGP MESSAGE-----\n"));
+        assert_eq!(backend.decrypt_symmetric(message.as_bytes(), PASSPHRASE).unwrap(),
+                   PLAINTEXT);
+        assert!(backend.decrypt_symmetric(message.as_bytes(), "1234").is_err());
+    }
+
+    #[test]
+    fn test_generate_key() {
+        let backend = OpenPgpBackend::new();
+        for &algorithm in &[KeyAlgorithm::Ed25519, KeyAlgorithm::Rsa(2048)] {
+            let pair = backend
+                .generate_key(&addr("alice@autocrypt.example"), algorithm)
+                .unwrap();
+            assert_eq!(OpenPgpBackend::public_key(&pair.secret).unwrap(), pair.public);
+            let key = TransferablePublicKey::from_bytes(pair.public.as_bytes()).unwrap();
+            ::validate_key(&key).unwrap();
+            backend.verify_key(&pair.public).unwrap();
+
+            let recipients = vec![pair.public.clone()];
+            let message = backend.encrypt(PLAINTEXT, &recipients, None).unwrap();
+            let decrypted = backend
+                .decrypt(message.as_bytes(), &pair.secret, &[])
+                .unwrap();
+            assert_eq!(decrypted.data, PLAINTEXT);
+            assert_eq!(decrypted.signature, SignatureStatus::Unsigned);
+        }
+
+        assert!(backend
+                    .generate_key(&addr("alice@autocrypt.example"), KeyAlgorithm::Rsa(1024))
+                    .is_err());
+    }
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let backend = OpenPgpBackend::new();
+        let senders = [public(&alice()), public(&bob())];
+
+        let message = backend
+            .encrypt(PLAINTEXT, &senders, Some(&alice()))
+            .unwrap();
+        for secret in &[alice(), bob()] {
+            let decrypted = backend
+                .decrypt(message.as_bytes(), secret, &senders)
+                .unwrap();
+            assert_eq!(decrypted.data, PLAINTEXT);
+            assert_eq!(decrypted.signature,
+                       SignatureStatus::Valid(pgp::fingerprint(&senders[0]).unwrap()));
+        }
+
+        // keys from Autocrypt headers
+        let key = KeyData::from_base64(&get_keydata("rsa2048-simple.eml")).unwrap();
+        assert!(backend.encrypt(PLAINTEXT, &[key], Some(&bob())).is_ok());
+
+        // modified ciphertext is detected
+        let mut binary = Vec::new();
+        Dearmor::new(message.as_bytes())
+            .read_to_end(&mut binary)
+            .unwrap();
+        let last = binary.len() - 1;
+        binary[last] ^= 1;
+        assert!(backend.decrypt(&binary, &alice(), &[]).is_err());
+    }
+
+    #[test]
+    fn test_sign_verify() {
+        let backend = OpenPgpBackend::new();
+        let senders = [public(&alice()), public(&bob())];
+
+        for (secret, key) in [alice(), bob()].iter().zip(&senders) {
+            let signature = backend.sign(PLAINTEXT, secret).unwrap();
+            assert!(signature.starts_with("-----BEGIN PGP SIGNATURE-----\n"));
+            assert_eq!(backend.verify(PLAINTEXT, signature.as_bytes(), &senders).unwrap(),
+                       SignatureStatus::Valid(pgp::fingerprint(key).unwrap()));
+            assert_eq!(backend.verify(b"other", signature.as_bytes(), &senders).unwrap(),
+                       SignatureStatus::Invalid);
+        }
+
+        let signature = backend.sign(PLAINTEXT, &alice()).unwrap();
+        assert_eq!(backend.verify(PLAINTEXT, signature.as_bytes(), &senders[1..]).unwrap(),
+                   SignatureStatus::UnknownKey(pgp::fingerprint(&senders[0]).unwrap().key_id()));
+    }
+
+    #[test]
+    fn test_signature_by_encryption_subkey() {
+        let backend = OpenPgpBackend::new();
+        let key = parse_secret_key(&bob()).unwrap();
+        let signature = DetachedSignature::sign_binary_data(thread_rng(),
+                                                            &key.secret_subkeys[0].key,
+                                                            &Password::empty(),
+                                                            HashAlgorithm::Sha256,
+                                                            PLAINTEXT)
+                .unwrap()
+                .to_armored_string(ArmorOptions::default())
+                .unwrap();
+        assert_eq!(backend
+                       .verify(PLAINTEXT, signature.as_bytes(), &[public(&bob())])
+                       .unwrap(),
+                   SignatureStatus::Invalid);
+    }
+
+    #[test]
+    fn test_verify_key() {
+        let backend = OpenPgpBackend::new();
+        backend.verify_key(&public(&alice())).unwrap();
+        backend.verify_key(&public(&bob())).unwrap();
+        let key = KeyData::from_base64(&get_keydata("rsa2048-simple.eml")).unwrap();
+        backend.verify_key(&key).unwrap();
+
+        // a modified binding signature keeps its hash prefix
+        let mut bytes = key.as_bytes().to_vec();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        let forged = KeyData::new(bytes);
+        ::validate_key(&TransferablePublicKey::from_bytes(forged.as_bytes()).unwrap()).unwrap();
+        assert_eq!(backend.verify_key(&forged),
+                   Err(KeyValidationError::MissingEncryptionSubkey));
+    }
+
+    #[test]
+    fn test_message_size_limit() {
+        let mut builder = MessageBuilder::from_bytes("", vec![0u8; 1 << 16]);
+        builder.compression(CompressionAlgorithm::ZLIB);
+        let message = builder.to_vec(thread_rng()).unwrap();
+        assert!(message.len() < 1 << 10);
+
+        let read = |limit| read_message(Message::from_bytes(&message[..]).unwrap(), &[], limit);
+        assert_eq!(read(1 << 16).unwrap().0.len(), 1 << 16);
+        assert!(read((1 << 16) - 1).is_err());
+    }
+}
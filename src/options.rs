@@ -0,0 +1,142 @@
+use std::fmt;
+
+use types::SpecVersion;
+
+/// How to deal with headers that are slightly broken, but still usable.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum ParsePolicy {
+    /// Reject anything that does not follow the header grammar exactly.
+    #[default]
+    Strict,
+    /// Accept broken headers where possible, reporting `ParseWarning`s.
+    Lenient,
+}
+
+/// Options for parsing headers.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct ParseOptions {
+    pub policy: ParsePolicy,
+    pub spec: SpecVersion,
+    /// Recognize the legacy `to` and `type` attributes, see `Dialect`. Only
+    /// `type=p` and `type=1` are accepted, other values are still rejected.
+    pub legacy: bool,
+    pub limits: Limits,
+}
+
+impl ParseOptions {
+    /// Strict parsing of Level 1 headers, as used by `FromStr`.
+    pub fn strict() -> ParseOptions {
+        ParseOptions::default()
+    }
+
+    /// Lenient parsing of Level 1 headers.
+    pub fn lenient() -> ParseOptions {
+        ParseOptions { policy: ParsePolicy::Lenient, ..ParseOptions::default() }
+    }
+
+    /// Use the given spec version.
+    pub fn spec(mut self, spec: SpecVersion) -> ParseOptions {
+        self.spec = spec;
+        self
+    }
+
+    /// Whether to recognize legacy headers.
+    pub fn legacy(mut self, legacy: bool) -> ParseOptions {
+        self.legacy = legacy;
+        self
+    }
+
+    /// Use the given limits.
+    pub fn limits(mut self, limits: Limits) -> ParseOptions {
+        self.limits = limits;
+        self
+    }
+}
+
+/// Bounds on the size of parsed headers, protecting against senders trying
+/// to exhaust memory or CPU.
+///
+/// Exceeding a limit is an error regardless of the `ParsePolicy`.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Limits {
+    /// Maximum size of the header value, in bytes.
+    pub max_header_size: usize,
+    /// Maximum number of attributes, including critical ones.
+    pub max_attributes: usize,
+    /// Maximum length of a single attribute other than `keydata`, in bytes.
+    pub max_attribute_length: usize,
+    /// Maximum size of the decoded `keydata`, in bytes.
+    pub max_key_size: usize,
+    /// Maximum number of Autocrypt headers in a single email.
+    pub max_headers: usize,
+}
+
+impl Default for Limits {
+    /// Limits leaving plenty of room for any key suitable for Autocrypt.
+    fn default() -> Limits {
+        Limits {
+            max_header_size: 64 * 1024,
+            max_attributes: 32,
+            max_attribute_length: 1024,
+            max_key_size: 32 * 1024,
+            max_headers: 16,
+        }
+    }
+}
+
+/// Problems accepted when parsing leniently, rejected when parsing strictly.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum ParseWarning {
+    /// A critical attribute appeared more than once, only the last value is
+    /// used. Non-critical attributes may repeat.
+    DuplicateAttribute { name: String, ignored: String },
+    /// An empty attribute, for example because of a trailing semicolon.
+    EmptyAttribute,
+    /// An attribute without a value, which was ignored.
+    MalformedAttribute(String),
+    /// `keydata` contains whitespace that is not folding whitespace.
+    KeydataWhitespace,
+}
+
+impl ParseWarning {
+    /// The name of the attribute this warning is about, if known.
+    pub fn attribute(&self) -> Option<&str> {
+        match *self {
+            ParseWarning::DuplicateAttribute { ref name, .. } => Some(name),
+            ParseWarning::KeydataWhitespace => Some("keydata"),
+            ParseWarning::EmptyAttribute |
+            ParseWarning::MalformedAttribute(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for ParseWarning {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseWarning::DuplicateAttribute { ref name, ref ignored } => {
+                write!(fmt, "Duplicate attribute {}, ignored value {:?}", name, ignored)
+            }
+            ParseWarning::EmptyAttribute => write!(fmt, "Empty attribute"),
+            ParseWarning::MalformedAttribute(ref attribute) => {
+                write!(fmt, "Malformed attribute {:?}", attribute)
+            }
+            ParseWarning::KeydataWhitespace => write!(fmt, "Stray whitespace in keydata"),
+        }
+    }
+}
+
+/// A parsed value, together with the warnings produced parsing it.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Parsed<T> {
+    pub value: T,
+    pub warnings: Vec<ParseWarning>,
+}
+
+impl<T> Parsed<T> {
+    pub fn map<U, F: FnOnce(T) -> U>(self, f: F) -> Parsed<U> {
+        Parsed {
+            value: f(self.value),
+            warnings: self.warnings,
+        }
+    }
+}
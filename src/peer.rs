@@ -1,9 +1,14 @@
 use time::{self, Tm, Duration};
 use std::fmt;
 use email::MimeMessage;
-use mime::{get_effective_date, get_ac_header};
+use mime::{get_effective_date, get_ac_header_with_options, get_ac_header_verified};
+use options::{ParseOptions, Parsed};
+use header::{Header, GossipHeader};
+use crypto::CryptoBackend;
 use types::{KeyType, EncryptPreference, Recommendation};
-use errors::PeerInfoParseError;
+use keydata::KeyData;
+use pgp::{TransferablePublicKey, Fingerprint};
+use errors::{PeerInfoParseError, HeaderParseError, KeyParseError};
 
 /// Internal state kept about a single peer
 #[derive(PartialEq, Eq, Debug, Clone)]
@@ -14,7 +19,7 @@ pub struct PeerInfo {
     /// UTC timestamp of the most recent effective date of all processed messages that contained a valid Autocrypt header.
     pub last_seen_autocrypt: Option<Tm>,
     /// The public key of this peer.
-    pub public_key: Option<String>,
+    pub public_key: Option<KeyData>,
     /// The current encryption preference.
     pub state: PeerState,
     /// For which autocrypt level this record is.
@@ -45,7 +50,7 @@ impl fmt::Display for PeerState {
 impl PeerInfo {
     pub fn new(seen: Tm,
                seen_ac: Option<Tm>,
-               key: Option<String>,
+               key: Option<KeyData>,
                state: Option<PeerState>)
                -> PeerInfo {
         PeerInfo {
@@ -58,9 +63,56 @@ impl PeerInfo {
         }
     }
 
+    /// Parse the OpenPGP key of this peer, if one is known.
+    pub fn parse_key(&self) -> Option<Result<TransferablePublicKey, KeyParseError>> {
+        self.public_key
+            .as_ref()
+            .map(|key| TransferablePublicKey::from_bytes(key.as_bytes()))
+    }
+
+    /// The fingerprint of the primary key of this peer, if one is known.
+    pub fn fingerprint(&self) -> Option<Result<Fingerprint, KeyParseError>> {
+        self.parse_key().map(|key| key.map(|key| key.fingerprint()))
+    }
+
     /// Update the current `PeerInfo` based on the passed in email.
     /// This manipulates the `self` in place.
-    pub fn update<'a>(&mut self, mail: &MimeMessage) -> Result<(), PeerInfoParseError> {
+    ///
+    /// The Autocrypt header is parsed strictly with the default `Limits`.
+    /// Its key is only checked structurally, see `validate_key`, use
+    /// `update_verified` to have its signatures checked as well.
+    pub fn update(&mut self, mail: &MimeMessage) -> Result<(), PeerInfoParseError> {
+        self.update_with_options(mail, &ParseOptions::strict())
+    }
+
+    /// Update the current `PeerInfo` based on the passed in email, parsing
+    /// the Autocrypt header with the given options.
+    pub fn update_with_options(&mut self,
+                               mail: &MimeMessage,
+                               options: &ParseOptions)
+                               -> Result<(), PeerInfoParseError> {
+        self.update_from(mail, |mail| get_ac_header_with_options(mail, options))
+    }
+
+    /// Update the current `PeerInfo` based on the passed in email, parsing
+    /// the Autocrypt header with the given options and checking its key with
+    /// `backend`, see `get_ac_header_verified`.
+    pub fn update_verified<B>(&mut self,
+                              backend: &B,
+                              mail: &MimeMessage,
+                              options: &ParseOptions)
+                              -> Result<(), PeerInfoParseError>
+        where B: CryptoBackend + ?Sized
+    {
+        self.update_from(mail, |mail| get_ac_header_verified(backend, mail, options))
+    }
+
+    fn update_from<F>(&mut self,
+                      mail: &MimeMessage,
+                      get_header: F)
+                      -> Result<(), PeerInfoParseError>
+        where F: FnOnce(&MimeMessage) -> Result<Parsed<Option<Header>>, HeaderParseError>
+    {
         // multipart/report content type is to be ignored
         let content_type: String = mail.headers.get_value("Content-Type".to_string())?;
         if content_type == "multipart/report" {
@@ -68,7 +120,7 @@ impl PeerInfo {
         }
 
         let eff_date = get_effective_date(mail);
-        let ac_header = get_ac_header(mail)?;
+        let ac_header = get_header(mail)?.value;
 
         if let Some(last_seen_ac) = self.last_seen_autocrypt {
             if eff_date < last_seen_ac {
@@ -104,6 +156,21 @@ impl PeerInfo {
         Ok(())
     }
 
+    /// Update the current `PeerInfo` with an Autocrypt-Gossip header about
+    /// this peer, such as returned by `DecryptedMessage::gossip_headers`.
+    ///
+    /// Only one key is kept per peer, so the gossiped key is only taken if
+    /// the peer never sent an Autocrypt header of their own, in which case
+    /// the state becomes `Gossip`.
+    pub fn update_gossip(&mut self, gossip: &GossipHeader) {
+        if self.last_seen_autocrypt.is_some() {
+            return;
+        }
+
+        self.public_key = Some(gossip.keydata().clone());
+        self.state = PeerState::Gossip;
+    }
+
     /// Get the autocrypt recommendation based on `self` being the from details.
     pub fn recommendation(&self, to: &PeerInfo) -> Recommendation {
         if to.public_key.is_none() {
@@ -149,8 +216,18 @@ impl PeerInfo {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::error::Error;
+    use errors::KeyValidationError;
+    use options::Limits;
     use helpers;
     use mime;
+    use header::HeaderBuilder;
+    use crypto::KeyAlgorithm;
+    use mock::MockBackend;
+
+    fn pubkey() -> KeyData {
+        KeyData::new(b"mypublickey".to_vec())
+    }
 
     #[test]
     fn test_update_report() {
@@ -174,6 +251,92 @@ mod tests {
                        .to_utc());
         assert!(p1.public_key.is_some());
         assert_eq!(p1.state, PeerState::None);
+
+        let key = p1.parse_key().unwrap().expect("failed to parse key");
+        assert_eq!(key.user_ids[0].user_id.as_str(), "alice@testsuite.autocrypt.org");
+        assert_eq!(p1.fingerprint().unwrap().unwrap().to_hex(),
+                   "7E2E3B36419A8BDB46F6106BBAFC533CD993BD7F");
+    }
+
+    #[test]
+    fn test_update_invalid_key() {
+        let mut p1 = PeerInfo::new(time::now_utc(), None, None, None);
+        let file = helpers::get_file("rsa2048-simple.eml");
+        let mail = mime::parse(&file.replace("keydata=\n mQENBFhV", "keydata=\n mQENBFhW"))
+            .expect("failed to parse");
+
+        let before = p1.clone();
+        let err = p1.update(&mail).unwrap_err();
+        assert_eq!(before, p1);
+
+        // the cause can be followed down to the validation error
+        let header_err = err.source().unwrap();
+        assert!(header_err.downcast_ref::<HeaderParseError>().is_some());
+        let key_err = header_err.source().unwrap();
+        assert!(key_err.downcast_ref::<KeyValidationError>().is_some());
+    }
+
+    #[test]
+    fn test_update_verified() {
+        let backend = MockBackend::new();
+        let pair = backend
+            .generate_key(&helpers::addr("alice@mail.com"), KeyAlgorithm::Ed25519)
+            .unwrap();
+        let mail_with_key = |keydata: KeyData| {
+            let header = HeaderBuilder::new()
+                .addr("alice@mail.com")
+                .keydata(keydata)
+                .build()
+                .unwrap();
+            let mut raw = "From: alice@mail.com\r\nDate: Sat, 17 Dec 2016 10:07:48 +0100\r\n\
+                           Content-Type: text/plain\r\n\r\nhello\r\n"
+                    .to_string()
+                    .into_bytes();
+            raw = mime::set_ac_header_raw(&raw, &header).unwrap();
+            mime::parse(&String::from_utf8(raw).unwrap()).unwrap()
+        };
+
+        let mut p1 = PeerInfo::new(time::now_utc(), None, None, None);
+        p1.update_verified(&backend, &mail_with_key(pair.public.clone()), &ParseOptions::strict())
+            .expect("failed to update");
+        assert_eq!(p1.public_key, Some(pair.public.clone()));
+
+        // the last byte of the fake subkey binding signature
+        let mut forged = pair.public.as_bytes().to_vec();
+        let last = forged.len() - 1;
+        forged[last] ^= 1;
+        let mail = mail_with_key(KeyData::new(forged));
+
+        let mut p2 = PeerInfo::new(time::now_utc(), None, None, None);
+        p2.update(&mail).expect("only the structure is checked");
+        assert!(p2.public_key.is_some());
+
+        let mut p3 = PeerInfo::new(time::now_utc(), None, None, None);
+        let before = p3.clone();
+        match p3.update_verified(&backend, &mail, &ParseOptions::strict()) {
+            Err(PeerInfoParseError::HeaderError(HeaderParseError::InvalidKey(err))) => {
+                assert_eq!(err, KeyValidationError::Unverified("forged mock signature".to_string()))
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(before, p3);
+    }
+
+    #[test]
+    fn test_update_key_too_large() {
+        let mut p1 = PeerInfo::new(time::now_utc(), None, None, None);
+        let file = helpers::get_file("rsa2048-simple.eml");
+        let mail = mime::parse(&file).expect("failed to parse");
+        let options = ParseOptions::strict().limits(Limits { max_key_size: 1024, ..Limits::default() });
+
+        let before = p1.clone();
+        match p1.update_with_options(&mail, &options) {
+            Err(PeerInfoParseError::HeaderError(HeaderParseError::KeyTooLarge { limit, .. })) => {
+                assert_eq!(limit, 1024)
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(before, p1);
     }
 
     #[test]
@@ -199,15 +362,15 @@ mod tests {
         let p1 = PeerInfo::new(time::now_utc(), None, None, None);
         let p2 = PeerInfo::new(time::now_utc(),
                                None,
-                               Some("mypublickey".to_string()),
+                               Some(pubkey()),
                                Some(PeerState::Gossip));
         let p3 = PeerInfo::new(time::now_utc(),
                                None,
-                               Some("pubkey".to_string()),
+                               Some(pubkey()),
                                Some(PeerState::Mutual));
         let p4 = PeerInfo::new(time::now_utc(),
                                Some(time::now_utc() - Duration::days(100)),
-                               Some("pubkey".to_string()),
+                               Some(pubkey()),
                                Some(PeerState::Reset));
 
         // No to public key
@@ -227,15 +390,15 @@ mod tests {
     fn test_recommendation_many() {
         let from = PeerInfo::new(time::now_utc(),
                                  Some(time::now_utc()),
-                                 Some("mypublickey".to_string()),
+                                 Some(pubkey()),
                                  Some(PeerState::Mutual));
         let to1 = PeerInfo::new(time::now_utc(),
                                 Some(time::now_utc()),
-                                Some("mypublickey".to_string()),
+                                Some(pubkey()),
                                 Some(PeerState::Mutual));
         let to2 = PeerInfo::new(time::now_utc(),
                                 Some(time::now_utc()),
-                                Some("mypublickey".to_string()),
+                                Some(pubkey()),
                                 Some(PeerState::Mutual));
         assert_eq!(from.recommendation_many(vec![&to1, &to2]),
                    Recommendation::Encrypt);
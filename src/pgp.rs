@@ -0,0 +1,1253 @@
+//! Parsing of OpenPGP transferable public keys, as found in `keydata`.
+//!
+//! Only the structure of the key is inspected, see RFC 4880 and RFC 9580 for
+//! the packet formats. No cryptographic verification happens here.
+
+use std::fmt;
+use std::borrow::Cow;
+use time::{self, Tm, Timespec, Duration};
+use sha1::Sha1;
+use sha2::{Digest, Sha224, Sha256, Sha384, Sha512};
+
+use keydata::KeyData;
+use errors::KeyParseError;
+
+const TAG_SIGNATURE: u8 = 2;
+const TAG_PUBLIC_KEY: u8 = 6;
+const TAG_TRUST: u8 = 12;
+const TAG_USER_ID: u8 = 13;
+const TAG_PUBLIC_SUBKEY: u8 = 14;
+const TAG_USER_ATTRIBUTE: u8 = 17;
+
+/// Public key algorithms, as assigned by RFC 4880 and RFC 9580.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum PublicKeyAlgorithm {
+    RSA,
+    RSAEncrypt,
+    RSASign,
+    ElGamal,
+    DSA,
+    ECDH,
+    ECDSA,
+    EdDSA,
+    X25519,
+    X448,
+    Ed25519,
+    Ed448,
+    Unknown(u8),
+}
+
+impl PublicKeyAlgorithm {
+    /// Whether keys of this algorithm can be used to create signatures.
+    pub fn can_sign(&self) -> bool {
+        matches!(*self,
+                 PublicKeyAlgorithm::RSA |
+                 PublicKeyAlgorithm::RSASign |
+                 PublicKeyAlgorithm::DSA |
+                 PublicKeyAlgorithm::ECDSA |
+                 PublicKeyAlgorithm::EdDSA |
+                 PublicKeyAlgorithm::Ed25519 |
+                 PublicKeyAlgorithm::Ed448)
+    }
+
+    /// Whether keys of this algorithm can be used to encrypt.
+    pub fn can_encrypt(&self) -> bool {
+        matches!(*self,
+                 PublicKeyAlgorithm::RSA |
+                 PublicKeyAlgorithm::RSAEncrypt |
+                 PublicKeyAlgorithm::ElGamal |
+                 PublicKeyAlgorithm::ECDH |
+                 PublicKeyAlgorithm::X25519 |
+                 PublicKeyAlgorithm::X448)
+    }
+}
+
+impl From<u8> for PublicKeyAlgorithm {
+    fn from(id: u8) -> PublicKeyAlgorithm {
+        match id {
+            1 => PublicKeyAlgorithm::RSA,
+            2 => PublicKeyAlgorithm::RSAEncrypt,
+            3 => PublicKeyAlgorithm::RSASign,
+            16 => PublicKeyAlgorithm::ElGamal,
+            17 => PublicKeyAlgorithm::DSA,
+            18 => PublicKeyAlgorithm::ECDH,
+            19 => PublicKeyAlgorithm::ECDSA,
+            22 => PublicKeyAlgorithm::EdDSA,
+            25 => PublicKeyAlgorithm::X25519,
+            26 => PublicKeyAlgorithm::X448,
+            27 => PublicKeyAlgorithm::Ed25519,
+            28 => PublicKeyAlgorithm::Ed448,
+            _ => PublicKeyAlgorithm::Unknown(id),
+        }
+    }
+}
+
+impl From<PublicKeyAlgorithm> for u8 {
+    fn from(algorithm: PublicKeyAlgorithm) -> u8 {
+        match algorithm {
+            PublicKeyAlgorithm::RSA => 1,
+            PublicKeyAlgorithm::RSAEncrypt => 2,
+            PublicKeyAlgorithm::RSASign => 3,
+            PublicKeyAlgorithm::ElGamal => 16,
+            PublicKeyAlgorithm::DSA => 17,
+            PublicKeyAlgorithm::ECDH => 18,
+            PublicKeyAlgorithm::ECDSA => 19,
+            PublicKeyAlgorithm::EdDSA => 22,
+            PublicKeyAlgorithm::X25519 => 25,
+            PublicKeyAlgorithm::X448 => 26,
+            PublicKeyAlgorithm::Ed25519 => 27,
+            PublicKeyAlgorithm::Ed448 => 28,
+            PublicKeyAlgorithm::Unknown(id) => id,
+        }
+    }
+}
+
+impl fmt::Display for PublicKeyAlgorithm {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PublicKeyAlgorithm::RSA => write!(fmt, "RSA"),
+            PublicKeyAlgorithm::RSAEncrypt => write!(fmt, "RSA (encrypt only)"),
+            PublicKeyAlgorithm::RSASign => write!(fmt, "RSA (sign only)"),
+            PublicKeyAlgorithm::ElGamal => write!(fmt, "ElGamal"),
+            PublicKeyAlgorithm::DSA => write!(fmt, "DSA"),
+            PublicKeyAlgorithm::ECDH => write!(fmt, "ECDH"),
+            PublicKeyAlgorithm::ECDSA => write!(fmt, "ECDSA"),
+            PublicKeyAlgorithm::EdDSA => write!(fmt, "EdDSA"),
+            PublicKeyAlgorithm::X25519 => write!(fmt, "X25519"),
+            PublicKeyAlgorithm::X448 => write!(fmt, "X448"),
+            PublicKeyAlgorithm::Ed25519 => write!(fmt, "Ed25519"),
+            PublicKeyAlgorithm::Ed448 => write!(fmt, "Ed448"),
+            PublicKeyAlgorithm::Unknown(id) => write!(fmt, "unknown ({})", id),
+        }
+    }
+}
+
+/// Hash algorithms, as assigned by RFC 4880 and RFC 9580.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum HashAlgorithm {
+    MD5,
+    SHA1,
+    RIPEMD160,
+    SHA256,
+    SHA384,
+    SHA512,
+    SHA224,
+    SHA3_256,
+    SHA3_512,
+    Unknown(u8),
+}
+
+impl From<u8> for HashAlgorithm {
+    fn from(id: u8) -> HashAlgorithm {
+        match id {
+            1 => HashAlgorithm::MD5,
+            2 => HashAlgorithm::SHA1,
+            3 => HashAlgorithm::RIPEMD160,
+            8 => HashAlgorithm::SHA256,
+            9 => HashAlgorithm::SHA384,
+            10 => HashAlgorithm::SHA512,
+            11 => HashAlgorithm::SHA224,
+            12 => HashAlgorithm::SHA3_256,
+            14 => HashAlgorithm::SHA3_512,
+            _ => HashAlgorithm::Unknown(id),
+        }
+    }
+}
+
+/// Elliptic curves used by ECDH, ECDSA and EdDSA keys.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Curve {
+    Ed25519,
+    Curve25519,
+    NistP256,
+    NistP384,
+    NistP521,
+    Unknown(Vec<u8>),
+}
+
+impl Curve {
+    fn from_oid(oid: &[u8]) -> Curve {
+        match oid {
+            [0x2B, 0x06, 0x01, 0x04, 0x01, 0xDA, 0x47, 0x0F, 0x01] => Curve::Ed25519,
+            [0x2B, 0x06, 0x01, 0x04, 0x01, 0x97, 0x55, 0x01, 0x05, 0x01] => Curve::Curve25519,
+            [0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x03, 0x01, 0x07] => Curve::NistP256,
+            [0x2B, 0x81, 0x04, 0x00, 0x22] => Curve::NistP384,
+            [0x2B, 0x81, 0x04, 0x00, 0x23] => Curve::NistP521,
+            _ => Curve::Unknown(oid.to_vec()),
+        }
+    }
+
+    /// Size of the curve in bits.
+    pub fn bits(&self) -> Option<usize> {
+        match *self {
+            Curve::Ed25519 | Curve::Curve25519 => Some(255),
+            Curve::NistP256 => Some(256),
+            Curve::NistP384 => Some(384),
+            Curve::NistP521 => Some(521),
+            Curve::Unknown(_) => None,
+        }
+    }
+}
+
+/// The type of a signature, see RFC 4880, section 5.2.1.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum SignatureType {
+    GenericCertification,
+    PersonaCertification,
+    CasualCertification,
+    PositiveCertification,
+    SubkeyBinding,
+    PrimaryKeyBinding,
+    DirectKey,
+    KeyRevocation,
+    SubkeyRevocation,
+    CertificationRevocation,
+    Other(u8),
+}
+
+impl SignatureType {
+    /// Whether this signature certifies a user id.
+    pub fn is_certification(&self) -> bool {
+        matches!(*self,
+                 SignatureType::GenericCertification |
+                 SignatureType::PersonaCertification |
+                 SignatureType::CasualCertification |
+                 SignatureType::PositiveCertification)
+    }
+}
+
+impl From<u8> for SignatureType {
+    fn from(id: u8) -> SignatureType {
+        match id {
+            0x10 => SignatureType::GenericCertification,
+            0x11 => SignatureType::PersonaCertification,
+            0x12 => SignatureType::CasualCertification,
+            0x13 => SignatureType::PositiveCertification,
+            0x18 => SignatureType::SubkeyBinding,
+            0x19 => SignatureType::PrimaryKeyBinding,
+            0x1F => SignatureType::DirectKey,
+            0x20 => SignatureType::KeyRevocation,
+            0x28 => SignatureType::SubkeyRevocation,
+            0x30 => SignatureType::CertificationRevocation,
+            _ => SignatureType::Other(id),
+        }
+    }
+}
+
+/// The key flags subpacket, see RFC 4880, section 5.2.3.21.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct KeyFlags(u8);
+
+impl KeyFlags {
+    pub fn new(bits: u8) -> KeyFlags {
+        KeyFlags(bits)
+    }
+
+    pub fn bits(&self) -> u8 {
+        self.0
+    }
+
+    pub fn can_certify(&self) -> bool {
+        self.0 & 0x01 != 0
+    }
+
+    pub fn can_sign(&self) -> bool {
+        self.0 & 0x02 != 0
+    }
+
+    pub fn can_encrypt_communications(&self) -> bool {
+        self.0 & 0x04 != 0
+    }
+
+    pub fn can_encrypt_storage(&self) -> bool {
+        self.0 & 0x08 != 0
+    }
+
+    /// Whether either of the encryption flags is set.
+    pub fn can_encrypt(&self) -> bool {
+        self.can_encrypt_communications() || self.can_encrypt_storage()
+    }
+
+    pub fn can_authenticate(&self) -> bool {
+        self.0 & 0x20 != 0
+    }
+}
+
+/// The fingerprint of a public key or subkey.
+#[derive(PartialEq, Eq, Debug, Clone, Hash)]
+pub enum Fingerprint {
+    /// SHA-1 fingerprint of a version 4 key.
+    V4([u8; 20]),
+    /// SHA-256 fingerprint of a version 6 key.
+    V6([u8; 32]),
+}
+
+impl Fingerprint {
+    pub fn as_bytes(&self) -> &[u8] {
+        match *self {
+            Fingerprint::V4(ref fp) => fp,
+            Fingerprint::V6(ref fp) => fp,
+        }
+    }
+
+    /// The key id, which is the low 64 bits of a version 4 fingerprint and
+    /// the high 64 bits of a version 6 fingerprint.
+    pub fn key_id(&self) -> KeyId {
+        let bytes = self.as_bytes();
+        let id = match *self {
+            Fingerprint::V4(_) => &bytes[12..],
+            Fingerprint::V6(_) => &bytes[..8],
+        };
+
+        let mut key_id = [0u8; 8];
+        key_id.copy_from_slice(id);
+        KeyId(key_id)
+    }
+
+    /// Uppercase hex, without any separators.
+    pub fn to_hex(&self) -> String {
+        to_hex(self.as_bytes())
+    }
+}
+
+/// Formats the fingerprint in blocks, the way other OpenPGP tools print it.
+///
+/// Version 4 fingerprints are grouped into blocks of four characters, with
+/// a wider gap in the middle. Version 6 fingerprints use blocks of eight.
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let hex = self.to_hex();
+        let block = match *self {
+            Fingerprint::V4(_) => 4,
+            Fingerprint::V6(_) => 8,
+        };
+
+        for (i, chunk) in hex.as_bytes().chunks(block).enumerate() {
+            if i > 0 {
+                write!(fmt, " ")?;
+                if block == 4 && i == 5 {
+                    write!(fmt, " ")?;
+                }
+            }
+            write!(fmt, "{}", String::from_utf8_lossy(chunk))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The 64 bit key id of a public key or subkey.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Hash)]
+pub struct KeyId([u8; 8]);
+
+impl KeyId {
+    pub fn new(bytes: [u8; 8]) -> KeyId {
+        KeyId(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Uppercase hex, without any separators.
+    pub fn to_hex(&self) -> String {
+        to_hex(&self.0)
+    }
+}
+
+impl fmt::Display for KeyId {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", self.to_hex())
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+/// Compute the fingerprint of the primary key in `keydata`.
+pub fn fingerprint(keydata: &KeyData) -> Result<Fingerprint, KeyParseError> {
+    TransferablePublicKey::from_bytes(keydata.as_bytes()).map(|key| key.primary.fingerprint())
+}
+
+/// A public key or subkey packet.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct PublicKey {
+    /// Packet version, `4` or `6`.
+    pub version: u8,
+    /// Creation time of the key.
+    pub created: Tm,
+    pub algorithm: PublicKeyAlgorithm,
+    /// Curve of elliptic curve keys.
+    pub curve: Option<Curve>,
+    /// Size of the key in bits, if known for the algorithm.
+    pub bits: Option<usize>,
+    body: Vec<u8>,
+}
+
+impl PublicKey {
+    /// The raw packet body.
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// Compute the fingerprint, see RFC 9580, section 5.5.4.
+    pub fn fingerprint(&self) -> Fingerprint {
+        if self.version == 6 {
+            let mut hasher = Sha256::new();
+            hasher.update(self.hash_header());
+            hasher.update(&self.body);
+            Fingerprint::V6(hasher.finalize().into())
+        } else {
+            let mut hasher = Sha1::new();
+            hasher.update(self.hash_header());
+            hasher.update(&self.body);
+            Fingerprint::V4(hasher.finalize().into())
+        }
+    }
+
+    /// The prefix used when hashing the key for fingerprints and signatures.
+    fn hash_header(&self) -> Vec<u8> {
+        let len = self.body.len();
+        let mut header = Vec::with_capacity(5);
+        if self.version == 6 {
+            header.push(0x9B);
+            header.extend_from_slice(&(len as u32).to_be_bytes());
+        } else {
+            header.push(0x99);
+            header.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+
+        header
+    }
+
+    pub fn key_id(&self) -> KeyId {
+        self.fingerprint().key_id()
+    }
+}
+
+/// A user id packet.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct UserId(Vec<u8>);
+
+impl UserId {
+    /// The user id, replacing any invalid UTF-8.
+    pub fn as_str(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.0)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Display for UserId {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", self.as_str())
+    }
+}
+
+/// A version 4 or 6 signature packet.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Signature {
+    pub version: u8,
+    pub typ: SignatureType,
+    pub algorithm: PublicKeyAlgorithm,
+    pub hash_algorithm: HashAlgorithm,
+    /// Signature creation time.
+    pub created: Option<Tm>,
+    /// Validity period of the signed key, relative to its creation time.
+    pub key_expiration: Option<Duration>,
+    pub key_flags: Option<KeyFlags>,
+    /// Whether the primary user id flag is set.
+    pub primary_user_id: bool,
+    /// The key id of the issuer.
+    pub issuer: Option<Vec<u8>>,
+    /// The fingerprint of the issuer, without the version octet.
+    pub issuer_fingerprint: Option<Vec<u8>>,
+    /// The left 16 bits of the signed hash.
+    pub hash_prefix: [u8; 2],
+    /// The salt of version 6 signatures.
+    pub salt: Vec<u8>,
+    body: Vec<u8>,
+    hashed_len: usize,
+}
+
+/// What a signature over a key is made on, in addition to the primary key.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum SignatureTarget<'a> {
+    /// Direct key signatures and key revocations.
+    PrimaryKey,
+    /// Certifications of a user id.
+    UserId(&'a UserId),
+    /// Subkey bindings and revocations.
+    Subkey(&'a PublicKey),
+}
+
+impl Signature {
+    /// The raw packet body.
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// The hashed part of the packet body, which is covered by the
+    /// signature.
+    pub fn hashed_area(&self) -> &[u8] {
+        &self.body[..self.hashed_len]
+    }
+
+    /// Whether the issuer of this signature is the given key, based on the
+    /// issuer fingerprint or key id subpackets.
+    pub fn is_issued_by(&self, key: &PublicKey) -> bool {
+        let fingerprint = key.fingerprint();
+        if let Some(ref issuer) = self.issuer_fingerprint {
+            return issuer.as_slice() == fingerprint.as_bytes();
+        }
+        if let Some(ref issuer) = self.issuer {
+            return issuer.as_slice() == fingerprint.key_id().as_bytes();
+        }
+
+        false
+    }
+
+    /// Compute the digest that this signature signs, see RFC 9580, section
+    /// 5.2.4.
+    ///
+    /// Returns `None` if the hash algorithm is not supported.
+    pub fn digest(&self, primary: &PublicKey, target: SignatureTarget) -> Option<Vec<u8>> {
+        let mut hasher = new_hasher(self.hash_algorithm)?;
+
+        hasher.update(&self.salt);
+        hasher.update(&primary.hash_header());
+        hasher.update(&primary.body);
+        match target {
+            SignatureTarget::PrimaryKey => {}
+            SignatureTarget::UserId(uid) => {
+                hasher.update(&[0xB4]);
+                hasher.update(&(uid.0.len() as u32).to_be_bytes());
+                hasher.update(&uid.0);
+            }
+            SignatureTarget::Subkey(subkey) => {
+                hasher.update(&subkey.hash_header());
+                hasher.update(&subkey.body);
+            }
+        }
+        hasher.update(self.hashed_area());
+        hasher.update(&[self.version, 0xFF]);
+        hasher.update(&(self.hashed_len as u32).to_be_bytes());
+
+        Some(hasher.finalize().into_vec())
+    }
+
+    /// Check the stored left 16 bits of the hash against the computed
+    /// digest. This detects corrupted or mismatched signatures, but is no
+    /// cryptographic verification.
+    ///
+    /// Returns `None` if the hash algorithm is not supported.
+    pub fn hash_prefix_matches(&self, primary: &PublicKey, target: SignatureTarget) -> Option<bool> {
+        self.digest(primary, target)
+            .map(|digest| digest[..2] == self.hash_prefix)
+    }
+
+    /// Whether this is a signature by `primary` over `target`, judged by its
+    /// issuer and the left 16 bits of its hash.
+    ///
+    /// This is no cryptographic verification. Signatures using a hash
+    /// algorithm that is not supported are rejected, as their hash cannot be
+    /// checked.
+    pub fn is_self_signature(&self, primary: &PublicKey, target: SignatureTarget) -> bool {
+        self.is_issued_by(primary) && self.hash_prefix_matches(primary, target) == Some(true)
+    }
+}
+
+fn new_hasher(algorithm: HashAlgorithm) -> Option<Box<dyn sha2::digest::DynDigest>> {
+    match algorithm {
+        HashAlgorithm::SHA1 => Some(Box::new(Sha1::default())),
+        HashAlgorithm::SHA224 => Some(Box::new(Sha224::default())),
+        HashAlgorithm::SHA256 => Some(Box::new(Sha256::default())),
+        HashAlgorithm::SHA384 => Some(Box::new(Sha384::default())),
+        HashAlgorithm::SHA512 => Some(Box::new(Sha512::default())),
+        _ => None,
+    }
+}
+
+/// A user id together with its signatures.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct SignedUserId {
+    pub user_id: UserId,
+    pub signatures: Vec<Signature>,
+}
+
+/// A subkey together with its binding and revocation signatures.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct SignedSubkey {
+    pub key: PublicKey,
+    pub signatures: Vec<Signature>,
+}
+
+impl SignedSubkey {
+    /// The key flags of the most recent binding signature.
+    pub fn key_flags(&self) -> Option<KeyFlags> {
+        latest(self.signatures
+                   .iter()
+                   .filter(|s| s.typ == SignatureType::SubkeyBinding))
+            .and_then(|s| s.key_flags)
+    }
+
+    /// Whether this subkey is usable for encryption, based on its key flags,
+    /// or its algorithm if no flags are present.
+    pub fn can_encrypt(&self) -> bool {
+        self.key_flags()
+            .map(|flags| flags.can_encrypt())
+            .unwrap_or_else(|| self.key.algorithm.can_encrypt())
+    }
+}
+
+/// An OpenPGP transferable public key, see RFC 4880, section 11.1.
+///
+/// User attribute packets are skipped, together with their signatures.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct TransferablePublicKey {
+    pub primary: PublicKey,
+    /// Direct key signatures and revocations of the primary key.
+    pub signatures: Vec<Signature>,
+    pub user_ids: Vec<SignedUserId>,
+    pub subkeys: Vec<SignedSubkey>,
+}
+
+impl TransferablePublicKey {
+    /// Parse a binary transferable public key.
+    pub fn from_bytes(bytes: &[u8]) -> Result<TransferablePublicKey, KeyParseError> {
+        let mut packets = PacketReader::new(bytes);
+
+        let primary = match packets.next_packet()? {
+            Some((TAG_PUBLIC_KEY, body)) => parse_public_key(body)?,
+            Some((tag, _)) => return Err(KeyParseError::UnexpectedPacket(tag)),
+            None => return Err(KeyParseError::MissingPrimaryKey),
+        };
+
+        let mut key = TransferablePublicKey {
+            primary,
+            signatures: Vec::new(),
+            user_ids: Vec::new(),
+            subkeys: Vec::new(),
+        };
+
+        // Signatures belong to the most recent component.
+        enum Component {
+            Primary,
+            UserId,
+            UserAttribute,
+            Subkey,
+        }
+        let mut current = Component::Primary;
+
+        while let Some((tag, body)) = packets.next_packet()? {
+            match tag {
+                TAG_SIGNATURE => {
+                    let sig = parse_signature(body)?;
+                    match current {
+                        Component::Primary => key.signatures.push(sig),
+                        Component::UserId => {
+                            key.user_ids.last_mut().expect("user id").signatures.push(sig)
+                        }
+                        Component::UserAttribute => {}
+                        Component::Subkey => {
+                            key.subkeys.last_mut().expect("subkey").signatures.push(sig)
+                        }
+                    }
+                }
+                TAG_USER_ID => {
+                    key.user_ids
+                        .push(SignedUserId {
+                                  user_id: UserId(body.to_vec()),
+                                  signatures: Vec::new(),
+                              });
+                    current = Component::UserId;
+                }
+                TAG_USER_ATTRIBUTE => current = Component::UserAttribute,
+                TAG_PUBLIC_SUBKEY => {
+                    key.subkeys
+                        .push(SignedSubkey {
+                                  key: parse_public_key(body)?,
+                                  signatures: Vec::new(),
+                              });
+                    current = Component::Subkey;
+                }
+                TAG_TRUST => {}
+                _ => return Err(KeyParseError::UnexpectedPacket(tag)),
+            }
+        }
+
+        Ok(key)
+    }
+
+    /// Serialize into a binary transferable public key.
+    ///
+    /// Packets are written in the old format, the way GnuPG does.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+
+        write_packet(&mut out, TAG_PUBLIC_KEY, &self.primary.body);
+        for sig in &self.signatures {
+            write_packet(&mut out, TAG_SIGNATURE, &sig.body);
+        }
+        for uid in &self.user_ids {
+            write_packet(&mut out, TAG_USER_ID, &uid.user_id.0);
+            for sig in &uid.signatures {
+                write_packet(&mut out, TAG_SIGNATURE, &sig.body);
+            }
+        }
+        for subkey in &self.subkeys {
+            write_packet(&mut out, TAG_PUBLIC_SUBKEY, &subkey.key.body);
+            for sig in &subkey.signatures {
+                write_packet(&mut out, TAG_SIGNATURE, &sig.body);
+            }
+        }
+
+        out
+    }
+
+    /// The key flags of the primary key, taken from the most recent self
+    /// signature on the primary user id, or a direct key signature.
+    ///
+    /// Self signatures are recognized by `Signature::is_self_signature`.
+    pub fn primary_key_flags(&self) -> Option<KeyFlags> {
+        let primary = &self.primary;
+        let uid_sigs = self.user_ids
+            .iter()
+            .flat_map(|uid| {
+                          uid.signatures
+                              .iter()
+                              .filter(move |s| {
+                                          s.typ.is_certification() &&
+                                          s.is_self_signature(primary, SignatureTarget::UserId(&uid.user_id))
+                                      })
+                      });
+        let primary_sigs: Vec<&Signature> = uid_sigs.clone().filter(|s| s.primary_user_id).collect();
+
+        let sig = if primary_sigs.is_empty() {
+            latest(uid_sigs)
+        } else {
+            latest(primary_sigs.into_iter())
+        };
+
+        sig.and_then(|s| s.key_flags)
+            .or_else(|| {
+                         latest(self.signatures
+                                    .iter()
+                                    .filter(|s| {
+                                                s.typ == SignatureType::DirectKey &&
+                                                s.is_self_signature(primary, SignatureTarget::PrimaryKey)
+                                            }))
+                                 .and_then(|s| s.key_flags)
+                     })
+    }
+
+    /// The fingerprint of the primary key.
+    pub fn fingerprint(&self) -> Fingerprint {
+        self.primary.fingerprint()
+    }
+
+    /// The fingerprints of all subkeys.
+    pub fn subkey_fingerprints(&self) -> Vec<Fingerprint> {
+        self.subkeys.iter().map(|k| k.key.fingerprint()).collect()
+    }
+
+    /// All subkeys usable for encryption.
+    pub fn encryption_subkeys(&self) -> Vec<&SignedSubkey> {
+        self.subkeys.iter().filter(|k| k.can_encrypt()).collect()
+    }
+}
+
+pub(crate) fn write_packet(out: &mut Vec<u8>, tag: u8, body: &[u8]) {
+    let len = body.len();
+    let ctb = 0x80 | (tag << 2);
+
+    if len < 0x100 {
+        out.push(ctb);
+        out.push(len as u8);
+    } else if len < 0x1_0000 {
+        out.push(ctb | 1);
+        out.extend_from_slice(&(len as u16).to_be_bytes());
+    } else {
+        out.push(ctb | 2);
+        out.extend_from_slice(&(len as u32).to_be_bytes());
+    }
+    out.extend_from_slice(body);
+}
+
+fn latest<'a, I>(sigs: I) -> Option<&'a Signature>
+    where I: Iterator<Item = &'a Signature>
+{
+    sigs.max_by_key(|s| s.created.map(|t| t.to_timespec().sec))
+}
+
+/// Reads packets from a binary OpenPGP message.
+struct PacketReader<'a> {
+    data: Reader<'a>,
+}
+
+impl<'a> PacketReader<'a> {
+    fn new(data: &'a [u8]) -> PacketReader<'a> {
+        PacketReader { data: Reader::new(data) }
+    }
+
+    /// Read the next packet, returning its tag and body.
+    fn next_packet(&mut self) -> Result<Option<(u8, &'a [u8])>, KeyParseError> {
+        if self.data.is_empty() {
+            return Ok(None);
+        }
+
+        let ctb = self.data.u8()?;
+        if ctb & 0x80 == 0 {
+            return Err(KeyParseError::InvalidPacketHeader);
+        }
+
+        let (tag, len) = if ctb & 0x40 != 0 {
+            // new format
+            let tag = ctb & 0x3F;
+            let first = self.data.u8()? as usize;
+            let len = match first {
+                0..=191 => first,
+                192..=223 => ((first - 192) << 8) + self.data.u8()? as usize + 192,
+                255 => self.data.u32()? as usize,
+                _ => return Err(KeyParseError::UnsupportedLength),
+            };
+            (tag, len)
+        } else {
+            // old format
+            let tag = (ctb >> 2) & 0x0F;
+            let len = match ctb & 0x03 {
+                0 => self.data.u8()? as usize,
+                1 => self.data.u16()? as usize,
+                2 => self.data.u32()? as usize,
+                _ => return Err(KeyParseError::UnsupportedLength),
+            };
+            (tag, len)
+        };
+
+        Ok(Some((tag, self.data.take(len)?)))
+    }
+}
+
+/// A cursor over a byte slice.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], KeyParseError> {
+        if self.data.len() < len {
+            return Err(KeyParseError::UnexpectedEnd);
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, KeyParseError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, KeyParseError> {
+        let b = self.take(2)?;
+        Ok(u16::from(b[0]) << 8 | u16::from(b[1]))
+    }
+
+    fn u32(&mut self) -> Result<u32, KeyParseError> {
+        let b = self.take(4)?;
+        Ok(u32::from(b[0]) << 24 | u32::from(b[1]) << 16 | u32::from(b[2]) << 8 | u32::from(b[3]))
+    }
+
+    /// Read a multiprecision integer, returning its size in bits and value.
+    fn mpi(&mut self) -> Result<(usize, &'a [u8]), KeyParseError> {
+        let bits = self.u16()? as usize;
+        Ok((bits, self.take(bits.div_ceil(8))?))
+    }
+
+    /// Read an OID, prefixed by its length.
+    fn oid(&mut self) -> Result<&'a [u8], KeyParseError> {
+        let len = self.u8()? as usize;
+        self.take(len)
+    }
+}
+
+fn timestamp(secs: u32) -> Tm {
+    time::at_utc(Timespec::new(i64::from(secs), 0))
+}
+
+fn parse_public_key(body: &[u8]) -> Result<PublicKey, KeyParseError> {
+    let mut r = Reader::new(body);
+
+    let version = r.u8()?;
+    let created = r.u32()?;
+    let algorithm = PublicKeyAlgorithm::from(r.u8()?);
+    match version {
+        4 => {}
+        6 => {
+            let len = r.u32()? as usize;
+            r = Reader::new(r.take(len)?);
+        }
+        _ => return Err(KeyParseError::UnsupportedVersion("public key", version)),
+    }
+
+    let (curve, bits) = match algorithm {
+        PublicKeyAlgorithm::RSA |
+        PublicKeyAlgorithm::RSAEncrypt |
+        PublicKeyAlgorithm::RSASign |
+        PublicKeyAlgorithm::DSA |
+        PublicKeyAlgorithm::ElGamal => (None, Some(r.mpi()?.0)),
+        PublicKeyAlgorithm::ECDH | PublicKeyAlgorithm::ECDSA | PublicKeyAlgorithm::EdDSA => {
+            let curve = Curve::from_oid(r.oid()?);
+            r.mpi()?;
+            let bits = curve.bits();
+            (Some(curve), bits)
+        }
+        PublicKeyAlgorithm::X25519 => (None, Some(255)),
+        PublicKeyAlgorithm::Ed25519 => (None, Some(255)),
+        PublicKeyAlgorithm::X448 | PublicKeyAlgorithm::Ed448 => (None, Some(448)),
+        PublicKeyAlgorithm::Unknown(_) => (None, None),
+    };
+
+    Ok(PublicKey {
+           version,
+           created: timestamp(created),
+           algorithm,
+           curve,
+           bits,
+           body: body.to_vec(),
+       })
+}
+
+pub(crate) fn parse_signature(body: &[u8]) -> Result<Signature, KeyParseError> {
+    let mut r = Reader::new(body);
+
+    let version = r.u8()?;
+    if version != 4 && version != 6 {
+        return Err(KeyParseError::UnsupportedVersion("signature", version));
+    }
+
+    let mut sig = Signature {
+        version,
+        typ: SignatureType::from(r.u8()?),
+        algorithm: PublicKeyAlgorithm::from(r.u8()?),
+        hash_algorithm: HashAlgorithm::from(r.u8()?),
+        created: None,
+        key_expiration: None,
+        key_flags: None,
+        primary_user_id: false,
+        issuer: None,
+        issuer_fingerprint: None,
+        hash_prefix: [0, 0],
+        salt: Vec::new(),
+        body: body.to_vec(),
+        hashed_len: 0,
+    };
+
+    let area_len = |r: &mut Reader| -> Result<usize, KeyParseError> {
+        if version == 4 {
+            r.u16().map(|l| l as usize)
+        } else {
+            r.u32().map(|l| l as usize)
+        }
+    };
+
+    let hashed_len = area_len(&mut r)?;
+    parse_subpackets(r.take(hashed_len)?, true, &mut sig)?;
+    sig.hashed_len = body.len() - r.data.len();
+
+    let unhashed_len = area_len(&mut r)?;
+    parse_subpackets(r.take(unhashed_len)?, false, &mut sig)?;
+
+    let prefix = r.take(2)?;
+    sig.hash_prefix = [prefix[0], prefix[1]];
+
+    if version == 6 {
+        let len = r.u8()? as usize;
+        sig.salt = r.take(len)?.to_vec();
+    }
+
+    Ok(sig)
+}
+
+fn parse_subpackets(data: &[u8], hashed: bool, sig: &mut Signature) -> Result<(), KeyParseError> {
+    let mut r = Reader::new(data);
+
+    while !r.is_empty() {
+        let first = r.u8()? as usize;
+        let len = match first {
+            0..=191 => first,
+            192..=254 => ((first - 192) << 8) + r.u8()? as usize + 192,
+            _ => r.u32()? as usize,
+        };
+        if len == 0 {
+            return Err(KeyParseError::UnexpectedEnd);
+        }
+        let typ = r.u8()? & 0x7F;
+        let mut value = Reader::new(r.take(len - 1)?);
+
+        // Only the issuer may be taken from the unhashed area, everything
+        // else is not protected by the signature.
+        match (typ, hashed) {
+            (2, true) => sig.created = Some(timestamp(value.u32()?)),
+            (9, true) => sig.key_expiration = Some(Duration::seconds(i64::from(value.u32()?))),
+            (25, true) => sig.primary_user_id = value.u8()? != 0,
+            (27, true) => sig.key_flags = Some(KeyFlags(value.u8()?)),
+            (16, _) if sig.issuer.is_none() => sig.issuer = Some(value.take(8)?.to_vec()),
+            (33, _) if sig.issuer_fingerprint.is_none() => {
+                value.u8()?;
+                sig.issuer_fingerprint = Some(value.data.to_vec());
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use helpers;
+    use mime;
+
+    fn fixture_key(filename: &str) -> TransferablePublicKey {
+        let file = helpers::get_file(filename);
+        let mail = mime::parse(&file).expect("failed to parse");
+        let header = mime::get_ac_header(&mail)
+            .expect("failed to get ac header")
+            .unwrap();
+
+        TransferablePublicKey::from_bytes(header.keydata.as_bytes()).expect("failed to parse key")
+    }
+
+    #[test]
+    fn test_parse_ed25519() {
+        let key = fixture_key("ed25519-simple.eml");
+
+        assert_eq!(key.primary.version, 4);
+        assert_eq!(key.primary.algorithm, PublicKeyAlgorithm::EdDSA);
+        assert_eq!(key.primary.curve, Some(Curve::Ed25519));
+        assert_eq!(key.primary.bits, Some(255));
+        assert_eq!(key.primary.created.to_timespec().sec, 1481971693);
+        assert!(key.signatures.is_empty());
+
+        assert_eq!(key.user_ids.len(), 1);
+        let uid = &key.user_ids[0];
+        assert_eq!(uid.user_id.as_str(), "alice@testsuite.autocrypt.org");
+        assert_eq!(uid.signatures.len(), 1);
+        assert_eq!(uid.signatures[0].typ, SignatureType::PositiveCertification);
+        assert_eq!(uid.signatures[0].hash_algorithm, HashAlgorithm::SHA256);
+        assert_eq!(uid.signatures[0].hash_prefix, [0x4a, 0x8e]);
+        assert_eq!(uid.signatures[0].issuer,
+                   Some(vec![0xFC, 0x74, 0x4D, 0xA9, 0xFE, 0x73, 0xC3, 0xD4]));
+        assert_eq!(key.primary_key_flags(), Some(KeyFlags::new(0x03)));
+
+        assert_eq!(key.subkeys.len(), 1);
+        let subkey = &key.subkeys[0];
+        assert_eq!(subkey.key.algorithm, PublicKeyAlgorithm::ECDH);
+        assert_eq!(subkey.key.curve, Some(Curve::Curve25519));
+        assert_eq!(subkey.signatures[0].typ, SignatureType::SubkeyBinding);
+        assert!(subkey.key_flags().unwrap().can_encrypt_communications());
+        assert_eq!(key.encryption_subkeys().len(), 1);
+    }
+
+    #[test]
+    fn test_parse_rsa() {
+        let key = fixture_key("rsa2048-simple.eml");
+
+        assert_eq!(key.primary.algorithm, PublicKeyAlgorithm::RSA);
+        assert_eq!(key.primary.bits, Some(2048));
+        assert_eq!(key.primary.curve, None);
+        assert_eq!(key.user_ids.len(), 1);
+        assert_eq!(key.subkeys.len(), 1);
+        assert_eq!(key.subkeys[0].key.bits, Some(2048));
+        assert!(key.subkeys[0].can_encrypt());
+        assert!(key.primary_key_flags().unwrap().can_sign());
+    }
+
+    #[test]
+    fn test_primary_key_flags_ignore_third_party() {
+        let mut key = fixture_key("ed25519-simple.eml");
+
+        // a newer certification by another key, claiming other flags
+        let mut body = vec![4, 0x10, 22, 8, 0, 12];
+        body.extend_from_slice(&[5, 2, 0x7F, 0, 0, 0]);
+        body.extend_from_slice(&[2, 27, 0x0C]);
+        body.extend_from_slice(&[2, 25, 1]);
+        body.extend_from_slice(&[0, 10, 9, 16, 1, 2, 3, 4, 5, 6, 7, 8, 0, 0]);
+        let mut sig = parse_signature(&body).expect("failed to parse signature");
+        key.user_ids[0].signatures.push(sig.clone());
+        assert_eq!(key.primary_key_flags(), Some(KeyFlags::new(0x03)));
+
+        key.user_ids[0].signatures.truncate(1);
+        key.user_ids[0].signatures[0].key_flags = None;
+        sig.typ = SignatureType::DirectKey;
+        key.signatures.push(sig);
+        assert_eq!(key.primary_key_flags(), None);
+    }
+
+    #[test]
+    fn test_primary_key_flags_ignore_forged_issuer() {
+        let mut key = fixture_key("ed25519-simple.eml");
+        let key_id = key.primary.fingerprint().key_id();
+
+        // a newer certification claiming the primary key as issuer, whose
+        // hash does not match
+        let mut body = vec![4, 0x10, 22, 8, 0, 12];
+        body.extend_from_slice(&[5, 2, 0x7F, 0, 0, 0]);
+        body.extend_from_slice(&[2, 27, 0x0C]);
+        body.extend_from_slice(&[2, 25, 1]);
+        body.extend_from_slice(&[0, 10, 9, 16]);
+        body.extend_from_slice(key_id.as_bytes());
+        body.extend_from_slice(&[0, 0]);
+        let sig = parse_signature(&body).expect("failed to parse signature");
+        assert!(sig.is_issued_by(&key.primary));
+        key.user_ids[0].signatures.push(sig);
+        assert_eq!(key.primary_key_flags(), Some(KeyFlags::new(0x03)));
+    }
+
+    // Sample v6 certificate from RFC 9580, appendix A.3
+    const V6_CERT: &str = "xioGY4d/4xsAAAAg+U2nu0jWCmHlZ3BqZYfQMxmZu52JGggkLq2EVD34laPCsQYf\
+                           GwoAAABCBYJjh3/jAwsJBwUVCg4IDAIWAAKbAwIeCSIhBssYbE8GCaaX5NUt+mxy\
+                           KwwfHifBilZwj2Ul7Ce62azJBScJAgcCAAAAAK0oIBA+LX0ifsDm185Ecds2v8lw\
+                           gyU2kCcUmKfvBXbAf6rhRYWzuQOwEn7E/aLwIwRaLsdry0+VcallHhSu4RN6HWaE\
+                           QsiPlR4zxP/TP7mhfVEe7XWPxtnMUMtf15OyA51YBM4qBmOHf+MZAAAAIIaTJINn\
+                           +eUBXbki+PSAld2nhJh/LVmFsS+60WyvXkQ1wpsGGBsKAAAALAWCY4d/4wKbDCIh\
+                           BssYbE8GCaaX5NUt+mxyKwwfHifBilZwj2Ul7Ce62azJAAAAAAQBIKbpGG2dWTX8\
+                           j+VjFM21J0hqWlEg+bdiojWnKfA5AQpWUWtnNwDEM0g12vYxoWM8Y81W+bHBw805\
+                           I8kWVkXU6vFOi+HWvv/ira7ofJu16NnoUkhclkUrk0mXubZvyl4GBg==";
+
+    #[test]
+    fn test_fingerprint_v4() {
+        let key = fixture_key("ed25519-simple.eml");
+
+        let fp = key.fingerprint();
+        assert_eq!(fp.to_hex(), "26AA6751FD7668C42FA4924AFC744DA9FE73C3D4");
+        assert_eq!(fp.to_string(),
+                   "26AA 6751 FD76 68C4 2FA4  924A FC74 4DA9 FE73 C3D4");
+        assert_eq!(fp.key_id().to_string(), "FC744DA9FE73C3D4");
+        assert_eq!(key.subkey_fingerprints()[0].to_hex(),
+                   "4CF9C821B4871398AEFA96B2C844E3CB6CE55B79");
+
+        let key = fixture_key("rsa2048-simple.eml");
+        assert_eq!(key.fingerprint().to_hex(),
+                   "7E2E3B36419A8BDB46F6106BBAFC533CD993BD7F");
+        assert_eq!(key.subkeys[0].key.key_id().to_hex(), "BA86FCD91943312C");
+    }
+
+    #[test]
+    fn test_fingerprint_v6() {
+        let keydata: KeyData = V6_CERT.parse().expect("invalid base64");
+        let key = TransferablePublicKey::from_bytes(keydata.as_bytes())
+            .expect("failed to parse key");
+
+        assert_eq!(key.primary.version, 6);
+        assert_eq!(key.primary.algorithm, PublicKeyAlgorithm::Ed25519);
+        assert_eq!(key.subkeys[0].key.algorithm, PublicKeyAlgorithm::X25519);
+
+        let fp = fingerprint(&keydata).expect("failed to compute fingerprint");
+        assert_eq!(fp.to_hex(),
+                   "CB186C4F0609A697E4D52DFA6C722B0C1F1E27C18A56708F6525EC27BAD9ACC9");
+        assert_eq!(fp.to_string(),
+                   "CB186C4F 0609A697 E4D52DFA 6C722B0C 1F1E27C1 8A56708F 6525EC27 BAD9ACC9");
+        assert_eq!(fp.key_id().to_hex(), "CB186C4F0609A697");
+        assert_eq!(key.subkey_fingerprints()[0].to_hex(),
+                   "12C83F1E706F6308FE151A417743A1F033790E93E9978488D1DB378DA9930885");
+    }
+
+    #[test]
+    fn test_signature_hash_prefix() {
+        for filename in &["ed25519-simple.eml", "rsa2048-simple.eml"] {
+            let key = fixture_key(filename);
+            let uid = &key.user_ids[0];
+            let sig = &uid.signatures[0];
+            assert!(sig.is_issued_by(&key.primary));
+            assert_eq!(sig.hash_prefix_matches(&key.primary, SignatureTarget::UserId(&uid.user_id)),
+                       Some(true));
+
+            let subkey = &key.subkeys[0];
+            let sig = &subkey.signatures[0];
+            assert!(sig.is_issued_by(&key.primary));
+            assert!(!sig.is_issued_by(&subkey.key));
+            assert_eq!(sig.hash_prefix_matches(&key.primary, SignatureTarget::Subkey(&subkey.key)),
+                       Some(true));
+            // signed over the wrong data
+            assert_eq!(sig.hash_prefix_matches(&key.primary, SignatureTarget::PrimaryKey),
+                       Some(false));
+        }
+    }
+
+    #[test]
+    fn test_signature_hash_prefix_v6() {
+        let keydata: KeyData = V6_CERT.parse().expect("invalid base64");
+        let key = TransferablePublicKey::from_bytes(keydata.as_bytes())
+            .expect("failed to parse key");
+
+        let sig = &key.signatures[0];
+        assert_eq!(sig.typ, SignatureType::DirectKey);
+        assert_eq!(sig.salt.len(), 32);
+        assert!(sig.is_issued_by(&key.primary));
+        assert_eq!(sig.hash_prefix_matches(&key.primary, SignatureTarget::PrimaryKey),
+                   Some(true));
+
+        let subkey = &key.subkeys[0];
+        assert_eq!(subkey.signatures[0].hash_prefix_matches(&key.primary,
+                                                            SignatureTarget::Subkey(&subkey.key)),
+                   Some(true));
+    }
+
+    #[test]
+    fn test_to_bytes_roundtrip() {
+        for filename in &["ed25519-simple.eml", "rsa2048-simple.eml"] {
+            let keydata: KeyData = helpers::get_keydata(*filename).parse().unwrap();
+            let key = TransferablePublicKey::from_bytes(keydata.as_bytes()).unwrap();
+            assert_eq!(key.to_bytes(), keydata.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_primary_key_flags_ignores_third_party() {
+        let keydata: KeyData = helpers::get_file("ed25519-full.key").parse().unwrap();
+        let key = TransferablePublicKey::from_bytes(keydata.as_bytes()).unwrap();
+
+        // both user ids carry a newer certification without key flags
+        assert_eq!(key.user_ids[0].signatures.len(), 2);
+        assert_eq!(key.primary_key_flags(), Some(KeyFlags::new(0x03)));
+    }
+
+    #[test]
+    fn test_parse_garbage() {
+        assert_eq!(TransferablePublicKey::from_bytes(b"mykey").err(),
+                   Some(KeyParseError::InvalidPacketHeader));
+        assert_eq!(TransferablePublicKey::from_bytes(&[]).err(),
+                   Some(KeyParseError::MissingPrimaryKey));
+    }
+
+    #[test]
+    fn test_parse_truncated() {
+        let key = fixture_key("ed25519-simple.eml");
+        let mut bytes = vec![0x98, key.primary.body().len() as u8];
+        bytes.extend_from_slice(&key.primary.body()[..10]);
+
+        assert_eq!(TransferablePublicKey::from_bytes(&bytes).err(),
+                   Some(KeyParseError::UnexpectedEnd));
+    }
+
+    #[test]
+    fn test_parse_unexpected_packet() {
+        // a user id packet without a primary key
+        assert_eq!(TransferablePublicKey::from_bytes(&[0xB4, 0x01, b'a']).err(),
+                   Some(KeyParseError::UnexpectedPacket(TAG_USER_ID)));
+    }
+}
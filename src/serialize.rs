@@ -0,0 +1,403 @@
+//! Serde support, enabled by the `serde` feature.
+//!
+//! `Header` and `PeerInfo` are serialized as maps carrying a `version` field,
+//! so their representation can change without breaking stored data. Version 2
+//! of `Header` keeps its non default attributes as an ordered list of
+//! `[name, value]` pairs, version 1 stored them as a map. Enums use
+//! the same strings as the Autocrypt headers, `KeyData` is Base64 encoded and
+//! timestamps are written as RFC 3339 strings in UTC. Timestamps are read
+//! back from either RFC 3339 strings or unix seconds.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de::{self, Visitor, Unexpected};
+use serde::ser::SerializeStruct;
+use time::{self, Tm, Timespec};
+
+use addr::EmailAddress;
+use header::Header;
+use keydata::KeyData;
+use peer::{PeerInfo, PeerState};
+use types::{KeyType, EncryptPreference, Recommendation, SpecVersion};
+
+/// Version of the serialized representation of `Header`.
+const HEADER_VERSION: u32 = 2;
+/// Version of the serialized representation of `PeerInfo`.
+const PEER_VERSION: u32 = 1;
+
+macro_rules! string_enum {
+    ($typ:ident { $($variant:ident => $name:literal),* }) => {
+        impl Serialize for $typ {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(match *self {
+                                             $($typ::$variant => $name),*
+                                         })
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $typ {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                const VARIANTS: &[&str] = &[$($name),*];
+
+                let s = String::deserialize(deserializer)?;
+                match s.as_str() {
+                    $($name => Ok($typ::$variant),)*
+                    _ => Err(de::Error::unknown_variant(&s, VARIANTS)),
+                }
+            }
+        }
+    }
+}
+
+string_enum!(EncryptPreference { Mutual => "mutual", None => "nopreference" });
+string_enum!(PeerState {
+                 Mutual => "mutual",
+                 None => "nopreference",
+                 Reset => "reset",
+                 Gossip => "gossip"
+             });
+string_enum!(Recommendation {
+                 Disable => "disable",
+                 Discourage => "discourage",
+                 Available => "available",
+                 Encrypt => "encrypt"
+             });
+string_enum!(SpecVersion { Draft => "draft", Level1 => "level1" });
+
+impl Serialize for KeyType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        match s.as_str() {
+            "1" => Ok(KeyType::OpenPGP),
+            _ => Ok(KeyType::Unknown(s)),
+        }
+    }
+}
+
+impl Serialize for KeyData {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_base64())
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyData {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+impl Serialize for EmailAddress {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for EmailAddress {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+impl Serialize for Header {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Header", 7)?;
+        state.serialize_field("version", &HEADER_VERSION)?;
+        state.serialize_field("addr", &self.addr)?;
+        state.serialize_field("type", &self.typ)?;
+        state.serialize_field("prefer_encrypt", &self.prefer_encrypt)?;
+        state.serialize_field("keydata", &self.keydata)?;
+        state.serialize_field("spec", &self.spec)?;
+        state.serialize_field("attributes", &self.non_critical())?;
+        state.end()
+    }
+}
+
+#[derive(Deserialize)]
+struct HeaderRepr {
+    version: u32,
+    addr: EmailAddress,
+    #[serde(rename = "type")]
+    typ: KeyType,
+    prefer_encrypt: EncryptPreference,
+    keydata: KeyData,
+    spec: SpecVersion,
+    #[serde(default)]
+    attributes: Attributes,
+}
+
+/// Non default attributes, a list since version 2 and a map before.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Attributes {
+    List(Vec<(String, String)>),
+    Map(BTreeMap<String, String>),
+}
+
+impl Default for Attributes {
+    fn default() -> Attributes {
+        Attributes::List(Vec::new())
+    }
+}
+
+impl<'de> Deserialize<'de> for Header {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = HeaderRepr::deserialize(deserializer)?;
+        check_version(repr.version, HEADER_VERSION)?;
+
+        let attributes = match repr.attributes {
+            Attributes::List(list) => list,
+            Attributes::Map(map) => map.into_iter().collect(),
+        };
+        let mut header = Header::new(repr.addr,
+                                     repr.typ,
+                                     repr.prefer_encrypt,
+                                     repr.keydata,
+                                     attributes)
+                .map_err(de::Error::custom)?;
+        header.spec = repr.spec;
+
+        Ok(header)
+    }
+}
+
+impl Serialize for PeerInfo {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("PeerInfo", 6)?;
+        state.serialize_field("version", &PEER_VERSION)?;
+        state.serialize_field("last_seen", &Timestamp(self.last_seen))?;
+        state.serialize_field("last_seen_autocrypt",
+                             &self.last_seen_autocrypt.map(Timestamp))?;
+        state.serialize_field("public_key", &self.public_key)?;
+        state.serialize_field("state", &self.state)?;
+        state.serialize_field("type", &self.typ)?;
+        state.end()
+    }
+}
+
+#[derive(Deserialize)]
+struct PeerInfoRepr {
+    version: u32,
+    last_seen: Timestamp,
+    last_seen_autocrypt: Option<Timestamp>,
+    public_key: Option<KeyData>,
+    state: PeerState,
+    #[serde(rename = "type")]
+    typ: KeyType,
+}
+
+impl<'de> Deserialize<'de> for PeerInfo {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = PeerInfoRepr::deserialize(deserializer)?;
+        check_version(repr.version, PEER_VERSION)?;
+
+        let mut peer = PeerInfo::new(repr.last_seen.0,
+                                     repr.last_seen_autocrypt.map(|t| t.0),
+                                     repr.public_key,
+                                     Some(repr.state));
+        peer.typ = repr.typ;
+
+        Ok(peer)
+    }
+}
+
+/// Accept all versions up to and including `current`.
+fn check_version<E: de::Error>(version: u32, current: u32) -> Result<(), E> {
+    if version == 0 || version > current {
+        let expected = format!("version 1 to {}", current);
+        return Err(E::invalid_value(Unexpected::Unsigned(version.into()), &expected.as_str()));
+    }
+
+    Ok(())
+}
+
+/// A point in time, serialized as an RFC 3339 string.
+struct Timestamp(Tm);
+
+impl Serialize for Timestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&time::at_utc(self.0.to_timespec()).rfc3339())
+    }
+}
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(TimestampVisitor)
+    }
+}
+
+struct TimestampVisitor;
+
+impl<'de> Visitor<'de> for TimestampVisitor {
+    type Value = Timestamp;
+
+    fn expecting(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "an RFC 3339 timestamp or unix seconds")
+    }
+
+    fn visit_i64<E: de::Error>(self, secs: i64) -> Result<Timestamp, E> {
+        Ok(Timestamp(time::at_utc(Timespec::new(secs, 0))))
+    }
+
+    fn visit_u64<E: de::Error>(self, secs: u64) -> Result<Timestamp, E> {
+        if secs > i64::MAX as u64 {
+            return Err(E::invalid_value(Unexpected::Unsigned(secs), &self));
+        }
+        self.visit_i64(secs as i64)
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<Timestamp, E> {
+        parse_rfc3339(s)
+            .map(Timestamp)
+            .ok_or_else(|| E::invalid_value(Unexpected::Str(s), &self))
+    }
+}
+
+/// Parse an RFC 3339 timestamp, converted to UTC.
+///
+/// Fractional seconds are dropped.
+fn parse_rfc3339(s: &str) -> Option<Tm> {
+    if !s.is_ascii() || s.len() < 20 {
+        return None;
+    }
+
+    let (datetime, offset) = s.split_at(19);
+    let offset = offset.trim_start_matches(|c: char| c == '.' || c.is_ascii_digit());
+    let offset = match offset {
+        "Z" | "z" => 0,
+        _ if offset.len() == 6 && &offset[3..4] == ":" => {
+            let hours: i64 = offset[1..3].parse().ok()?;
+            let minutes: i64 = offset[4..6].parse().ok()?;
+            let secs = hours * 3600 + minutes * 60;
+            match &offset[..1] {
+                "+" => secs,
+                "-" => -secs,
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+
+    // `to_timespec` only treats a `Tm` as UTC when it carries no offset, so
+    // the offset is applied by hand
+    let tm = time::strptime(datetime, "%Y-%m-%dT%H:%M:%S").ok()?;
+    Some(time::at_utc(Timespec::new(tm.to_timespec().sec - offset, 0)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    fn tm(secs: i64) -> Tm {
+        time::at_utc(Timespec::new(secs, 0))
+    }
+
+    #[test]
+    fn test_enums() {
+        assert_eq!(serde_json::to_string(&EncryptPreference::None).unwrap(),
+                   "\"nopreference\"");
+        assert_eq!(serde_json::to_string(&PeerState::Gossip).unwrap(), "\"gossip\"");
+        assert_eq!(serde_json::to_string(&Recommendation::Discourage).unwrap(),
+                   "\"discourage\"");
+        assert_eq!(serde_json::to_string(&KeyType::OpenPGP).unwrap(), "\"1\"");
+
+        assert_eq!(serde_json::from_str::<EncryptPreference>("\"mutual\"").unwrap(),
+                   EncryptPreference::Mutual);
+        assert_eq!(serde_json::from_str::<Recommendation>("\"encrypt\"").unwrap(),
+                   Recommendation::Encrypt);
+        assert_eq!(serde_json::from_str::<KeyType>("\"p\"").unwrap(),
+                   KeyType::Unknown("p".to_string()));
+        assert!(serde_json::from_str::<PeerState>("\"sometimes\"").is_err());
+    }
+
+    #[test]
+    fn test_header_roundtrip() {
+        let attributes = vec![("_foo".to_string(), "one".to_string()),
+                              ("_bar".to_string(), "two".to_string()),
+                              ("_foo".to_string(), "three".to_string())];
+        let header = Header::new("me@mail.com".parse().unwrap(),
+                                 KeyType::OpenPGP,
+                                 EncryptPreference::Mutual,
+                                 KeyData::new(b"mykey".to_vec()),
+                                 attributes).unwrap();
+
+        let json = serde_json::to_string(&header).unwrap();
+        assert_eq!(json,
+                   "{\"version\":2,\"addr\":\"me@mail.com\",\"type\":\"1\",\
+                    \"prefer_encrypt\":\"mutual\",\"keydata\":\"bXlrZXk=\",\
+                    \"spec\":\"level1\",\"attributes\":[[\"_foo\",\"one\"],\
+                    [\"_bar\",\"two\"],[\"_foo\",\"three\"]]}");
+        assert_eq!(serde_json::from_str::<Header>(&json).unwrap(), header);
+
+        // attributes are validated like `HeaderBuilder` does
+        assert!(serde_json::from_str::<Header>(&json.replace("\"_bar\"", "\"bar\"")).is_err());
+        assert!(serde_json::from_str::<Header>(&json.replace("\"two\"", "\"t;wo\"")).is_err());
+    }
+
+    #[test]
+    fn test_header_version_1() {
+        let json = "{\"version\":1,\"addr\":\"me@mail.com\",\"type\":\"1\",\
+                    \"prefer_encrypt\":\"mutual\",\"keydata\":\"bXlrZXk=\",\
+                    \"spec\":\"level1\",\"attributes\":{\"_foo\":\"one\",\"_bar\":\"two\"}}";
+        let header: Header = serde_json::from_str(json).unwrap();
+        assert_eq!(header.to_string(),
+                   "addr=me@mail.com; prefer-encrypt=mutual; _bar=two; _foo=one; keydata=bXlrZXk=");
+    }
+
+    #[test]
+    fn test_peer_roundtrip() {
+        let peer = PeerInfo::new(tm(1481971694),
+                                 Some(tm(1481968094)),
+                                 Some(KeyData::new(b"mykey".to_vec())),
+                                 Some(PeerState::Mutual));
+
+        let json = serde_json::to_string(&peer).unwrap();
+        assert_eq!(json,
+                   "{\"version\":1,\"last_seen\":\"2016-12-17T10:48:14Z\",\
+                    \"last_seen_autocrypt\":\"2016-12-17T09:48:14Z\",\
+                    \"public_key\":\"bXlrZXk=\",\"state\":\"mutual\",\"type\":\"1\"}");
+        assert_eq!(serde_json::from_str::<PeerInfo>(&json).unwrap(), peer);
+    }
+
+    #[test]
+    fn test_peer_timestamps() {
+        let json = "{\"version\":1,\"last_seen\":1481971694,\
+                    \"last_seen_autocrypt\":\"2016-12-17T11:48:14.250+01:00\",\
+                    \"public_key\":null,\"state\":\"nopreference\",\"type\":\"1\"}";
+        let peer: PeerInfo = serde_json::from_str(json).unwrap();
+
+        assert_eq!(peer.last_seen, tm(1481971694));
+        assert_eq!(peer.last_seen_autocrypt, Some(tm(1481971694)));
+        assert_eq!(peer.public_key, None);
+
+        let json = json.replace("11:48:14.250+01:00", "08:18:14-02:30");
+        let peer: PeerInfo = serde_json::from_str(&json).unwrap();
+        assert_eq!(peer.last_seen_autocrypt, Some(tm(1481971694)));
+
+        assert!(serde_json::from_str::<PeerInfo>(&json.replace("-02:30", "-0230")).is_err());
+    }
+
+    #[test]
+    fn test_unknown_version() {
+        let json = "{\"version\":3,\"addr\":\"me@mail.com\",\"type\":\"1\",\
+                    \"prefer_encrypt\":\"mutual\",\"keydata\":\"bXlrZXk=\",\"spec\":\"level1\"}";
+        assert!(serde_json::from_str::<Header>(json).is_err());
+        assert!(serde_json::from_str::<Header>(&json.replace("me@mail.com", "me")).is_err());
+        assert!(serde_json::from_str::<Header>(&json.replace("\"version\":3", "\"version\":2"))
+                    .is_ok());
+
+        let json = "{\"version\":2,\"last_seen\":1481971694,\"last_seen_autocrypt\":null,\
+                    \"public_key\":null,\"state\":\"nopreference\",\"type\":\"1\"}";
+        assert!(serde_json::from_str::<PeerInfo>(json).is_err());
+    }
+}
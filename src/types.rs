@@ -24,16 +24,63 @@ impl str::FromStr for KeyType {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
+            "" => Err(KeyTypeParseError::Empty),
             "1" => Ok(KeyType::OpenPGP),
             _ => Ok(KeyType::Unknown(s.to_string())),
         }
     }
 }
 
+/// Which revision of the Autocrypt specification to follow when parsing
+/// and serializing headers.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum SpecVersion {
+    /// The pre 1.0 drafts, which carry a `type` attribute and always
+    /// emit `prefer-encrypt`.
+    Draft,
+    /// Autocrypt Level 1, as defined by the 1.0 and 1.1 specifications.
+    #[default]
+    Level1,
+}
+
+/// The dialect a header was written in.
+///
+/// Headers predating Autocrypt Level 1 are only recognized when parsing with
+/// `ParseOptions::legacy`, and are mapped onto the current attributes.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum Dialect {
+    /// The attributes of the spec version parsed with.
+    #[default]
+    Standard,
+    /// A legacy header using `to` in place of `addr`.
+    LegacyTo,
+    /// A legacy header using `type=p` in place of `type=1`.
+    LegacyType,
+    /// A legacy header using both `to` and `type=p`.
+    LegacyToAndType,
+}
+
+impl Dialect {
+    pub(crate) fn detect(legacy_to: bool, legacy_type: bool) -> Dialect {
+        match (legacy_to, legacy_type) {
+            (false, false) => Dialect::Standard,
+            (true, false) => Dialect::LegacyTo,
+            (false, true) => Dialect::LegacyType,
+            (true, true) => Dialect::LegacyToAndType,
+        }
+    }
+
+    /// Whether this is one of the legacy dialects.
+    pub fn is_legacy(&self) -> bool {
+        *self != Dialect::Standard
+    }
+}
+
 /// Possible values for encryption preference
-#[derive(PartialEq, Eq, Debug, Clone)]
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
 pub enum EncryptPreference {
     Mutual,
+    #[default]
     None,
 }
 
@@ -52,7 +99,8 @@ impl str::FromStr for EncryptPreference {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
             "mutual" => Ok(EncryptPreference::Mutual),
-            _ => Ok(EncryptPreference::None),
+            "nopreference" => Ok(EncryptPreference::None),
+            _ => Err(EncryptPreferenceParseError::Unknown(s.to_string())),
         }
     }
 }
@@ -65,3 +113,25 @@ pub enum Recommendation {
     Available,
     Encrypt,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_type_from_str() {
+        assert_eq!("1".parse::<KeyType>(), Ok(KeyType::OpenPGP));
+        assert_eq!("p".parse::<KeyType>(), Ok(KeyType::Unknown("p".to_string())));
+        assert_eq!("".parse::<KeyType>(), Err(KeyTypeParseError::Empty));
+    }
+
+    #[test]
+    fn test_encrypt_preference_from_str() {
+        assert_eq!("mutual".parse::<EncryptPreference>(),
+                   Ok(EncryptPreference::Mutual));
+        assert_eq!("nopreference".parse::<EncryptPreference>(),
+                   Ok(EncryptPreference::None));
+        assert_eq!("sometimes".parse::<EncryptPreference>(),
+                   Err(EncryptPreferenceParseError::Unknown("sometimes".to_string())));
+    }
+}
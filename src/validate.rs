@@ -0,0 +1,282 @@
+use time::{self, Tm, Duration};
+
+use pgp::{TransferablePublicKey, SignedSubkey, SignatureTarget, SignatureType, Signature,
+          PublicKey};
+use errors::KeyValidationError;
+
+/// Check that a key follows the rules of Autocrypt Level 1, section 2.1.1.
+///
+/// The key must consist of a signing capable primary key that is neither
+/// revoked nor expired, a user id with a self signature, and exactly one
+/// encryption capable subkey that is neither revoked nor expired, bound to
+/// the primary key.
+///
+/// This only checks the structure of the key. Signatures are matched by
+/// their issuer and the left 16 bits of their hash, see
+/// `Signature::is_self_signature`, which anyone can compute, so passing this
+/// check does not mean the key is authentic. Use
+/// `CryptoBackend::verify_key` to check the signatures as well.
+pub fn validate_key(key: &TransferablePublicKey) -> Result<(), KeyValidationError> {
+    let primary = &key.primary;
+
+    let flags = key.primary_key_flags();
+    if !primary.algorithm.can_sign() || flags.map(|f| !f.can_sign()).unwrap_or(false) {
+        return Err(KeyValidationError::PrimaryCannotSign(primary.algorithm));
+    }
+
+    if key.user_ids.is_empty() {
+        return Err(KeyValidationError::MissingUserId);
+    }
+
+    let self_signed = key.user_ids
+        .iter()
+        .any(|uid| {
+                 uid.signatures
+                     .iter()
+                     .filter(|sig| sig.typ.is_certification())
+                     .any(|sig| {
+                              sig.is_self_signature(primary, SignatureTarget::UserId(&uid.user_id))
+                          })
+             });
+    if !self_signed {
+        return Err(KeyValidationError::MissingSelfSignature);
+    }
+
+    let now = time::now_utc();
+    if latest_self_signature(&key.signatures,
+                             primary,
+                             SignatureTarget::PrimaryKey,
+                             |typ| typ == SignatureType::KeyRevocation)
+               .is_some() {
+        return Err(KeyValidationError::PrimaryRevoked);
+    }
+    if latest_primary_self_signature(key).map(|sig| is_expired(primary, sig, now)) == Some(true) {
+        return Err(KeyValidationError::PrimaryExpired);
+    }
+
+    let encryption_subkeys: Vec<_> = key.encryption_subkeys()
+        .into_iter()
+        .filter(|subkey| !is_revoked(subkey, primary))
+        .filter(|subkey| {
+                    latest_binding(subkey, primary)
+                        .map(|binding| !is_expired(&subkey.key, binding, now))
+                        .unwrap_or(true)
+                })
+        .collect();
+    match encryption_subkeys.len() {
+        0 => return Err(KeyValidationError::MissingEncryptionSubkey),
+        1 => {}
+        count => return Err(KeyValidationError::TooManyEncryptionSubkeys(count)),
+    }
+
+    let subkey = encryption_subkeys[0];
+    if latest_binding(subkey, primary).is_none() {
+        return Err(KeyValidationError::MissingBindingSignature(subkey.key.fingerprint()));
+    }
+
+    Ok(())
+}
+
+/// The most recent self signature of the given types, see
+/// `Signature::is_self_signature`.
+pub(crate) fn latest_self_signature<'a, F>(sigs: &'a [Signature],
+                                           primary: &PublicKey,
+                                           target: SignatureTarget,
+                                           typ: F)
+                                           -> Option<&'a Signature>
+    where F: Fn(SignatureType) -> bool
+{
+    sigs.iter()
+        .filter(|sig| typ(sig.typ) && sig.is_self_signature(primary, target))
+        .max_by_key(|sig| sig.created.map(|t| t.to_timespec().sec))
+}
+
+/// The most recent self signature over the primary key, a certification of
+/// any of its user ids or a direct key signature.
+fn latest_primary_self_signature(key: &TransferablePublicKey) -> Option<&Signature> {
+    let primary = &key.primary;
+    let certifications = key.user_ids.iter().filter_map(|uid| {
+        latest_self_signature(&uid.signatures,
+                              primary,
+                              SignatureTarget::UserId(&uid.user_id),
+                              |typ| typ.is_certification())
+    });
+    let direct = latest_self_signature(&key.signatures,
+                                       primary,
+                                       SignatureTarget::PrimaryKey,
+                                       |typ| typ == SignatureType::DirectKey);
+
+    certifications
+        .chain(direct)
+        .max_by_key(|sig| sig.created.map(|t| t.to_timespec().sec))
+}
+
+/// The most recent binding signature of a subkey.
+pub(crate) fn latest_binding<'a>(subkey: &'a SignedSubkey,
+                                 primary: &PublicKey)
+                                 -> Option<&'a Signature> {
+    latest_self_signature(&subkey.signatures,
+                          primary,
+                          SignatureTarget::Subkey(&subkey.key),
+                          |typ| typ == SignatureType::SubkeyBinding)
+}
+
+/// Whether a subkey was revoked by the primary key.
+pub(crate) fn is_revoked(subkey: &SignedSubkey, primary: &PublicKey) -> bool {
+    latest_self_signature(&subkey.signatures,
+                          primary,
+                          SignatureTarget::Subkey(&subkey.key),
+                          |typ| typ == SignatureType::SubkeyRevocation)
+            .is_some()
+}
+
+/// Whether a key has expired at `now`, according to the self signature
+/// `sig` over it.
+pub(crate) fn is_expired(key: &PublicKey, sig: &Signature, now: Tm) -> bool {
+    sig.key_expiration
+        .map(|validity| validity > Duration::zero() && key.created + validity < now)
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use helpers;
+    use keydata::KeyData;
+    use pgp::{KeyFlags, PublicKeyAlgorithm, HashAlgorithm, parse_signature};
+
+    fn fixture_key(filename: &str) -> TransferablePublicKey {
+        let keydata: KeyData = helpers::get_keydata(filename).parse().expect("invalid keydata");
+        TransferablePublicKey::from_bytes(keydata.as_bytes()).expect("failed to parse key")
+    }
+
+    #[test]
+    fn test_valid() {
+        validate_key(&fixture_key("ed25519-simple.eml")).expect("ed25519 key is valid");
+        validate_key(&fixture_key("rsa2048-simple.eml")).expect("rsa key is valid");
+    }
+
+    #[test]
+    fn test_missing_user_id() {
+        let mut key = fixture_key("ed25519-simple.eml");
+        key.user_ids.clear();
+        assert_eq!(validate_key(&key), Err(KeyValidationError::MissingUserId));
+    }
+
+    #[test]
+    fn test_missing_self_signature() {
+        let mut key = fixture_key("ed25519-simple.eml");
+        key.user_ids[0].signatures.clear();
+        assert_eq!(validate_key(&key),
+                   Err(KeyValidationError::MissingSelfSignature));
+    }
+
+    #[test]
+    fn test_foreign_self_signature() {
+        let mut key = fixture_key("ed25519-simple.eml");
+        let other = fixture_key("rsa2048-simple.eml");
+        key.user_ids[0].signatures = other.user_ids[0].signatures.clone();
+        assert_eq!(validate_key(&key),
+                   Err(KeyValidationError::MissingSelfSignature));
+    }
+
+    #[test]
+    fn test_primary_cannot_sign() {
+        let mut key = fixture_key("ed25519-simple.eml");
+        key.user_ids[0].signatures[0].key_flags = Some(KeyFlags::new(0x01));
+        assert_eq!(validate_key(&key),
+                   Err(KeyValidationError::PrimaryCannotSign(PublicKeyAlgorithm::EdDSA)));
+    }
+
+    #[test]
+    fn test_encryption_subkeys() {
+        let mut key = fixture_key("ed25519-simple.eml");
+        let subkey = key.subkeys[0].clone();
+
+        key.subkeys.push(subkey);
+        assert_eq!(validate_key(&key),
+                   Err(KeyValidationError::TooManyEncryptionSubkeys(2)));
+
+        key.subkeys.clear();
+        assert_eq!(validate_key(&key),
+                   Err(KeyValidationError::MissingEncryptionSubkey));
+    }
+
+    #[test]
+    fn test_rotated_subkeys() {
+        // an expired encryption subkey next to its replacement
+        let keydata: KeyData = helpers::get_file("ed25519-full.key").parse().unwrap();
+        let key = TransferablePublicKey::from_bytes(keydata.as_bytes()).unwrap();
+        assert_eq!(key.encryption_subkeys().len(), 2);
+        validate_key(&key).expect("expired subkeys are not counted");
+    }
+
+    #[test]
+    fn test_revoked_subkey() {
+        let mut key = fixture_key("ed25519-simple.eml");
+        let mut subkey = key.subkeys[0].clone();
+
+        let mut body = vec![4, 0x28, 22, 8, 0, 6, 5, 2, 0x7F, 0, 0, 0, 0, 10, 9, 16];
+        body.extend_from_slice(key.primary.fingerprint().key_id().as_bytes());
+        body.extend_from_slice(&[0, 0]);
+        let mut revocation = parse_signature(&body).expect("failed to parse signature");
+        let digest = revocation
+            .digest(&key.primary, SignatureTarget::Subkey(&subkey.key))
+            .unwrap();
+        revocation.hash_prefix = [digest[0], digest[1]];
+        subkey.signatures.push(revocation);
+
+        key.subkeys.push(subkey);
+        validate_key(&key).expect("revoked subkeys are not counted");
+    }
+
+    #[test]
+    fn test_revoked_primary() {
+        let mut key = fixture_key("ed25519-simple.eml");
+
+        let mut body = vec![4, 0x20, 22, 8, 0, 6, 5, 2, 0x7F, 0, 0, 0, 0, 10, 9, 16];
+        body.extend_from_slice(key.primary.fingerprint().key_id().as_bytes());
+        body.extend_from_slice(&[0, 0]);
+        let mut revocation = parse_signature(&body).expect("failed to parse signature");
+        key.signatures.push(revocation.clone());
+        validate_key(&key).expect("revocation with a wrong hash is ignored");
+
+        let digest = revocation.digest(&key.primary, SignatureTarget::PrimaryKey).unwrap();
+        revocation.hash_prefix = [digest[0], digest[1]];
+        key.signatures = vec![revocation];
+        assert_eq!(validate_key(&key), Err(KeyValidationError::PrimaryRevoked));
+    }
+
+    #[test]
+    fn test_expired_primary() {
+        let mut key = fixture_key("ed25519-simple.eml");
+        key.user_ids[0].signatures[0].key_expiration = Some(Duration::days(1));
+        assert_eq!(validate_key(&key), Err(KeyValidationError::PrimaryExpired));
+
+        key.user_ids[0].signatures[0].key_expiration = Some(Duration::zero());
+        validate_key(&key).expect("zero means the key does not expire");
+    }
+
+    #[test]
+    fn test_unsupported_hash() {
+        let mut key = fixture_key("ed25519-simple.eml");
+        key.subkeys[0].signatures[0].hash_algorithm = HashAlgorithm::SHA3_256;
+        let fingerprint = key.subkeys[0].key.fingerprint();
+        assert_eq!(validate_key(&key),
+                   Err(KeyValidationError::MissingBindingSignature(fingerprint)));
+
+        key.user_ids[0].signatures[0].hash_algorithm = HashAlgorithm::MD5;
+        assert_eq!(validate_key(&key),
+                   Err(KeyValidationError::MissingSelfSignature));
+    }
+
+    #[test]
+    fn test_missing_binding_signature() {
+        let mut key = fixture_key("ed25519-simple.eml");
+        let fingerprint = key.subkeys[0].key.fingerprint();
+        // the binding of another key
+        key.subkeys[0].signatures = fixture_key("rsa2048-simple.eml").subkeys[0].signatures.clone();
+        assert_eq!(validate_key(&key),
+                   Err(KeyValidationError::MissingBindingSignature(fingerprint)));
+    }
+}